@@ -0,0 +1,122 @@
+use crate::order::Order;
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use thirtyfour::prelude::*;
+
+/// 読み込んだ注文内容を注文番号ごとにファイルへ保存しておく
+/// 注文日から`final_after`経った注文はもう変わらないとみなして期限なしで使い、
+/// それより新しい注文は保存から`ttl`の間だけ使う
+#[derive(Debug, Clone)]
+pub struct OrderCache {
+    pub dir: PathBuf,
+    pub ttl: Duration,
+    pub final_after: Duration,
+    /// 注文内容ページのHTMLも一緒に保存する
+    pub keep_html: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    cached_at: DateTime<Utc>,
+    order: Order,
+}
+
+impl OrderCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> OrderCache {
+        OrderCache {
+            dir: dir.into(),
+            ttl: Duration::days(1),
+            final_after: Duration::days(60),
+            keep_html: false,
+        }
+    }
+    /// 使える状態のものがあれば返す
+    pub fn get(&self, order_id: &str) -> Option<Order> {
+        if order_id.is_empty() {
+            return None;
+        }
+        let json = fs::read_to_string(self.path(order_id, "json")).ok()?;
+        let entry: Entry = serde_json::from_str(&json).ok()?;
+        if self.is_usable(&entry, Utc::now()) {
+            Some(entry.order)
+        } else {
+            None
+        }
+    }
+    pub fn put(&self, order: &Order, html: Option<&str>) -> WebDriverResult<()> {
+        if order.id.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        let entry = Entry {
+            cached_at: Utc::now(),
+            order: order.clone(),
+        };
+        fs::write(
+            self.path(&order.id, "json"),
+            serde_json::to_string_pretty(&entry)?,
+        )?;
+        if let Some(html) = html {
+            fs::write(self.path(&order.id, "html"), html)?;
+        }
+        Ok(())
+    }
+    fn is_usable(&self, entry: &Entry, now: DateTime<Utc>) -> bool {
+        let today = now.with_timezone(&Local).date_naive();
+        today - entry.order.ordered_at >= self.final_after || now - entry.cached_at < self.ttl
+    }
+    fn path(&self, order_id: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", order_id, extension))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, OrderCache};
+    use crate::order::Order;
+    use chrono::{Duration, Local, Utc};
+
+    fn order(id: &str, days_ago: i64) -> Order {
+        Order::new(
+            id.to_string(),
+            Local::now().date_naive() - Duration::days(days_ago),
+            String::new(),
+        )
+    }
+
+    #[test]
+    fn 保存した注文を読み戻せるか確認() {
+        let dir = std::env::temp_dir().join(format!("amazon-log-cache-{}", std::process::id()));
+        let cache = OrderCache::new(&dir);
+        let saved = order("250-0000000-0000000", 3);
+        cache.put(&saved, None).unwrap();
+        let loaded = cache.get("250-0000000-0000000").unwrap();
+        assert_eq!(loaded.id, saved.id);
+        assert_eq!(loaded.ordered_at, saved.ordered_at);
+        assert!(cache.get("250-9999999-9999999").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn 古い注文は期限切れでも使い新しい注文は期限内だけ使うか確認() {
+        let cache = OrderCache::new("unused");
+        let now = Utc::now();
+        let stale = now - Duration::days(2);
+        let final_entry = Entry {
+            cached_at: stale,
+            order: order("final", 90),
+        };
+        let recent_entry = Entry {
+            cached_at: stale,
+            order: order("recent", 10),
+        };
+        let fresh_entry = Entry {
+            cached_at: now,
+            order: order("fresh", 10),
+        };
+        assert!(cache.is_usable(&final_entry, now));
+        assert!(!cache.is_usable(&recent_entry, now));
+        assert!(cache.is_usable(&fresh_entry, now));
+    }
+}
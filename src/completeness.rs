@@ -0,0 +1,105 @@
+use regex::Regex;
+use std::fmt;
+
+/// 1年分の注文履歴について、Amazonが表示している注文数と実際に辿った注文数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YearCount {
+    pub year: i32,
+    /// 注文履歴の見出しの「N件の注文」。見つからなければNone
+    pub reported: Option<usize>,
+    pub visited: usize,
+    /// 範囲の開始日で途中から辿るのをやめた年は比べられない
+    pub walked_to_end: bool,
+}
+
+impl YearCount {
+    pub fn is_consistent(&self) -> bool {
+        !self.walked_to_end
+            || self
+                .reported
+                .is_none_or(|reported| reported == self.visited)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompletenessReport {
+    pub years: Vec<YearCount>,
+}
+
+impl CompletenessReport {
+    pub fn mismatches(&self) -> Vec<&YearCount> {
+        self.years
+            .iter()
+            .filter(|count| !count.is_consistent())
+            .collect()
+    }
+    pub fn is_complete(&self) -> bool {
+        self.mismatches().is_empty()
+    }
+}
+
+impl fmt::Display for CompletenessReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for count in &self.years {
+            let mark = match (count.walked_to_end, count.is_consistent()) {
+                (false, _) => "--",
+                (true, true) => "OK",
+                (true, false) => "NG",
+            };
+            let reported = count
+                .reported
+                .map_or("?".to_string(), |reported| reported.to_string());
+            writeln!(
+                f,
+                "[{}] {}年 表示{}件 / 読み込み{}件",
+                mark, count.year, reported, count.visited
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// 「2021年に12件の注文」のような見出しから件数を読む
+pub(crate) fn reported_count_from_text(text: &str) -> Option<usize> {
+    let re = Regex::new(r"(\d[\d,]*)\s*件の注文").unwrap();
+    re.captures(text)
+        .and_then(|caps| caps[1].replace(',', "").parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reported_count_from_text, CompletenessReport, YearCount};
+
+    #[test]
+    fn 注文数の見出しを読めるか確認() {
+        assert_eq!(reported_count_from_text("2021年に12件の注文"), Some(12));
+        assert_eq!(reported_count_from_text("3 件の注文"), Some(3));
+        assert_eq!(
+            reported_count_from_text("2021年に1,234件の注文"),
+            Some(1234)
+        );
+        assert_eq!(reported_count_from_text("注文履歴"), None);
+    }
+    #[test]
+    fn 最後まで辿った年だけ件数を比べるか確認() {
+        let report = CompletenessReport {
+            years: vec![
+                YearCount {
+                    year: 2021,
+                    reported: Some(12),
+                    visited: 11,
+                    walked_to_end: true,
+                },
+                YearCount {
+                    year: 2020,
+                    reported: Some(30),
+                    visited: 4,
+                    walked_to_end: false,
+                },
+            ],
+        };
+        assert!(!report.is_complete());
+        assert_eq!(report.mismatches().len(), 1);
+        assert_eq!(report.mismatches()[0].year, 2021);
+    }
+}
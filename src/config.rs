@@ -0,0 +1,216 @@
+use crate::cache::OrderCache;
+use crate::extraction::Completion;
+use crate::rate_limit::RateLimit;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use thirtyfour::prelude::*;
+use tokio_util::sync::CancellationToken;
+
+// headlessのChromeは既定のユーザーエージェントに"HeadlessChrome"を含み、Amazonに弾かれる
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/97.0.4692.71 Safari/537.36";
+
+// 既定のプロファイル置き場。ユーザーごとのデータディレクトリの下に作る
+// 普段使いのブラウザのプロファイルを使う場合は`profile_root`に
+// "~/Library/Application Support/Google/Chrome"などを指定する
+fn default_profile_root(browser: BrowserKind) -> PathBuf {
+    let data_dir = if cfg!(target_os = "windows") {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+    };
+    let browser = match browser {
+        BrowserKind::Chrome => "chrome",
+        BrowserKind::Firefox => "firefox",
+    };
+    data_dir
+        .unwrap_or_else(std::env::temp_dir)
+        .join("amazon-log")
+        .join(browser)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrowserKind {
+    /// chromedriver
+    Chrome,
+    /// geckodriver
+    Firefox,
+}
+
+#[derive(Debug, Clone)]
+pub enum WebDriverServer {
+    /// 起動済みのWebDriverに接続する
+    Remote(String),
+    /// chromedriver/geckodriverを空いているポートで起動し、`quit`かdropで終了させる
+    /// パスが`None`の場合はPATHから探す
+    Managed(Option<PathBuf>),
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowserConfig {
+    pub browser: BrowserKind,
+    pub server: WebDriverServer,
+    pub user_data_dir: String,
+    /// `None`の場合はユーザーごとのデータディレクトリ(Linuxなら`~/.local/share/amazon-log/chrome`など)を使う
+    pub profile_root: Option<PathBuf>,
+    pub headless: bool,
+    /// `None`の場合、headlessのChromeのときだけ通常のデスクトップ版と同じものを使う
+    pub user_agent: Option<String>,
+    /// headlessの既定サイズ(800x600)だとレイアウトが変わりセレクタが合わなくなる
+    pub window_size: (u32, u32),
+    pub language: String,
+    /// ページ遷移後に要素が現れるまで待つ上限
+    pub wait_timeout: Duration,
+    /// すべてのページ遷移に適用する。`None`なら間隔を空けない
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl BrowserConfig {
+    pub fn new(user_data_dir: &str) -> BrowserConfig {
+        BrowserConfig {
+            browser: BrowserKind::Chrome,
+            server: WebDriverServer::Remote("http://localhost:4444".to_string()),
+            user_data_dir: user_data_dir.to_string(),
+            profile_root: None,
+            headless: false,
+            user_agent: None,
+            window_size: (1920, 1080),
+            language: "ja-JP".to_string(),
+            wait_timeout: Duration::from_secs(10),
+            rate_limit: None,
+        }
+    }
+    pub fn headless(user_data_dir: &str) -> BrowserConfig {
+        BrowserConfig {
+            headless: true,
+            ..BrowserConfig::new(user_data_dir)
+        }
+    }
+    pub fn firefox(user_data_dir: &str) -> BrowserConfig {
+        BrowserConfig {
+            browser: BrowserKind::Firefox,
+            ..BrowserConfig::new(user_data_dir)
+        }
+    }
+    pub fn profile_dir(&self) -> PathBuf {
+        let root = match &self.profile_root {
+            Some(root) => root.clone(),
+            None => default_profile_root(self.browser),
+        };
+        root.join(&self.user_data_dir)
+    }
+    // headlessのFirefoxは通常と同じユーザーエージェントを名乗るので差し替えない
+    pub(crate) fn effective_user_agent(&self) -> Option<&str> {
+        match (&self.user_agent, self.headless, self.browser) {
+            (Some(user_agent), _, _) => Some(user_agent),
+            (None, true, BrowserKind::Chrome) => Some(DEFAULT_USER_AGENT),
+            (None, _, _) => None,
+        }
+    }
+    pub(crate) fn chrome_capabilities(&self) -> WebDriverResult<ChromeCapabilities> {
+        let mut caps = DesiredCapabilities::chrome();
+        // 既定の置き場は初回はまだないので親ディレクトリごと作っておく
+        let profile_dir = self.profile_dir();
+        std::fs::create_dir_all(&profile_dir)?;
+        caps.add_chrome_arg(&format!("--user-data-dir={}", profile_dir.display()))?;
+        let (width, height) = self.window_size;
+        caps.add_chrome_arg(&format!("--window-size={},{}", width, height))?;
+        caps.add_chrome_arg(&format!("--lang={}", self.language))?;
+        caps.add_chrome_option(
+            "prefs",
+            serde_json::json!({ "intl.accept_languages": self.language }),
+        )?;
+        if let Some(user_agent) = self.effective_user_agent() {
+            caps.add_chrome_arg(&format!("--user-agent={}", user_agent))?;
+        }
+        if self.headless {
+            caps.set_headless()?;
+            caps.add_chrome_arg("--disable-gpu")?;
+        }
+        Ok(caps)
+    }
+    pub(crate) fn firefox_capabilities(&self) -> WebDriverResult<FirefoxCapabilities> {
+        // geckodriverは存在しないプロファイルを指定すると起動に失敗する
+        let profile_dir = self.profile_dir();
+        std::fs::create_dir_all(&profile_dir)?;
+
+        let mut caps = DesiredCapabilities::firefox();
+        caps.add_firefox_arg("-profile")?;
+        caps.add_firefox_arg(&profile_dir.display().to_string())?;
+        let (width, height) = self.window_size;
+        caps.add_firefox_arg(&format!("--width={}", width))?;
+        caps.add_firefox_arg(&format!("--height={}", height))?;
+        let mut prefs = serde_json::json!({ "intl.accept_languages": self.language });
+        if let Some(user_agent) = self.effective_user_agent() {
+            prefs["general.useragent.override"] = serde_json::json!(user_agent);
+        }
+        caps.add_firefox_option("prefs", prefs)?;
+        if self.headless {
+            caps.set_headless()?;
+        }
+        Ok(caps)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// 同じセッション内で同時に開く注文内容ページの数
+    pub detail_concurrency: usize,
+    /// 指定すると読み込んだ注文内容を保存し、次回以降は変わらない注文のページを開かない
+    pub cache: Option<OrderCache>,
+    /// キャンセルされると読み込み中の注文を終えたところで止める
+    pub cancel: Option<CancellationToken>,
+    /// この時刻を過ぎると読み込み中の注文を終えたところで止める
+    pub deadline: Option<Instant>,
+    /// Kindle本やアプリ、Prime Videoなどのデジタル注文も読み込む
+    pub include_digital: bool,
+    /// 注文ごとに領収書も開き、税率ごとの内訳と登録番号を読む
+    pub read_invoice: bool,
+    /// 指定すると注文ごとに領収書をこのディレクトリへPDFで保存する
+    pub invoice_pdf_dir: Option<PathBuf>,
+    /// 最後まで辿った年の注文数がAmazonの表示と合わなければエラーにする
+    pub require_complete: bool,
+    /// 非表示にした注文の一覧も辿る。読んだ注文は`Order::archived`になる
+    pub include_archived: bool,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            detail_concurrency: 1,
+            cache: None,
+            cancel: None,
+            deadline: None,
+            include_digital: false,
+            read_invoice: false,
+            invoice_pdf_dir: None,
+            require_complete: false,
+            include_archived: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    pub(crate) fn interruption(&self) -> Option<Completion> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.is_cancelled())
+        {
+            return Some(Completion::Cancelled);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Some(Completion::DeadlineExceeded);
+        }
+        None
+    }
+}
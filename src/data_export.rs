@@ -0,0 +1,370 @@
+use crate::details::digital_kind;
+use crate::order::{Order, OrderStatus, Source};
+use crate::product::ProductRef;
+use crate::{ItemKind, ItemStatus, Log, Payment, Shipment, ShipmentStatus};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Amazonの「データのリクエスト」でダウンロードしたZIP、またはその中のCSVから注文を読む
+/// `Retail.OrderHistory.*.csv`は物理商品、`Digital Items.csv`はデジタル注文として読み、
+/// 注文日の降順で返す
+pub fn import_data_export<P: AsRef<Path>>(path: P) -> io::Result<Vec<Order>> {
+    let path = path.as_ref();
+    let mut orders = vec![];
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = file.name().to_string();
+            if let Some(kind) = csv_kind(&name) {
+                let mut content = vec![];
+                file.read_to_end(&mut content)?;
+                read_csv(kind, content.as_slice(), &mut orders)?;
+            }
+        }
+    } else {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let kind = csv_kind(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("注文履歴のCSVではありません: {}", path.display()),
+            )
+        })?;
+        read_csv(kind, File::open(path)?, &mut orders)?;
+    }
+    orders.sort_by(|a, b| b.ordered_at.cmp(&a.ordered_at));
+    Ok(orders)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CsvKind {
+    Retail,
+    Digital,
+}
+
+fn csv_kind(name: &str) -> Option<CsvKind> {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    if !file_name.ends_with(".csv") {
+        None
+    } else if file_name.starts_with("Retail.OrderHistory") {
+        Some(CsvKind::Retail)
+    } else if file_name == "Digital Items.csv" {
+        Some(CsvKind::Digital)
+    } else {
+        None
+    }
+}
+
+fn read_csv<R: Read>(kind: CsvKind, reader: R, orders: &mut Vec<Order>) -> io::Result<()> {
+    let mut rows = csv::Reader::from_reader(reader);
+    match kind {
+        CsvKind::Retail => {
+            for row in rows.deserialize() {
+                add_retail_row(orders, row?);
+            }
+        }
+        CsvKind::Digital => {
+            for row in rows.deserialize() {
+                add_digital_row(orders, row?);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct RetailRow {
+    #[serde(rename = "Order ID")]
+    order_id: String,
+    #[serde(rename = "Order Date")]
+    order_date: String,
+    #[serde(rename = "Unit Price", default)]
+    unit_price: String,
+    #[serde(rename = "Unit Price Tax", default)]
+    unit_price_tax: String,
+    #[serde(rename = "Shipping Charge", default)]
+    shipping_charge: String,
+    #[serde(rename = "Total Discounts", default)]
+    total_discounts: String,
+    #[serde(rename = "Total Owed", default)]
+    total_owed: String,
+    #[serde(rename = "ASIN", default)]
+    asin: String,
+    #[serde(rename = "Quantity", default)]
+    quantity: String,
+    #[serde(rename = "Payment Instrument Type", default)]
+    payment_instrument_type: String,
+    #[serde(rename = "Order Status", default)]
+    order_status: String,
+    #[serde(rename = "Shipment Status", default)]
+    shipment_status: String,
+    #[serde(rename = "Carrier Name & Tracking Number", default)]
+    carrier_and_tracking: String,
+    #[serde(rename = "Product Name", default)]
+    product_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DigitalRow {
+    #[serde(rename = "OrderId")]
+    order_id: String,
+    #[serde(rename = "OrderDate")]
+    order_date: String,
+    #[serde(rename = "ASIN", default)]
+    asin: String,
+    #[serde(rename = "Title", default)]
+    title: String,
+    #[serde(rename = "OurPrice", default)]
+    our_price: String,
+    #[serde(rename = "OurPriceTax", default)]
+    our_price_tax: String,
+    #[serde(rename = "OriginalQuantity", default)]
+    original_quantity: String,
+    // 以下は種類の判別にだけ使う
+    #[serde(rename = "ProductName", default)]
+    product_name: String,
+    #[serde(rename = "Publisher", default)]
+    publisher: String,
+    #[serde(rename = "SellerOfRecord", default)]
+    seller_of_record: String,
+}
+
+fn add_retail_row(orders: &mut Vec<Order>, row: RetailRow) {
+    let ordered_at = match date_in_japan(&row.order_date) {
+        Some(date) => date,
+        None => return,
+    };
+    let details_url = format!(
+        "https://www.amazon.co.jp/gp/your-account/order-details?orderID={}",
+        row.order_id
+    );
+    let order = order_entry(orders, &row.order_id, ordered_at, details_url);
+    let cancelled = row.order_status.eq_ignore_ascii_case("Cancelled");
+    if cancelled {
+        order.status = OrderStatus::Cancelled;
+    }
+
+    let payment = order.payment.get_or_insert_with(Payment::default);
+    payment.shipping += to_yen(&row.shipping_charge);
+    payment.discount += to_yen(&row.total_discounts).abs();
+    payment.grand_total = Some(payment.grand_total.unwrap_or(0) + to_yen(&row.total_owed));
+    if payment.method.is_none() && !row.payment_instrument_type.is_empty() {
+        payment.method = Some(row.payment_instrument_type.clone());
+    }
+
+    if !row.carrier_and_tracking.is_empty() && row.carrier_and_tracking != "Not Available" {
+        let (carrier, tracking_id) = split_carrier(&row.carrier_and_tracking);
+        if !order
+            .shipments
+            .iter()
+            .any(|shipment| shipment.tracking_id == tracking_id)
+        {
+            order.shipments.push(Shipment {
+                status: match row.shipment_status.as_str() {
+                    "Delivered" => ShipmentStatus::Delivered,
+                    "Shipped" => ShipmentStatus::Shipped,
+                    _ => ShipmentStatus::Unknown,
+                },
+                delivered_at: None,
+                expected: None,
+                carrier,
+                tracking_id,
+                tracking_url: None,
+            });
+        }
+    }
+
+    let item = Log {
+        hash: row.asin.clone(),
+        name: row.product_name,
+        price: to_yen(&row.unit_price) + to_yen(&row.unit_price_tax),
+        purchased_at: ordered_at.to_string(),
+        kind: ItemKind::Physical,
+        status: if cancelled {
+            ItemStatus::Cancelled
+        } else {
+            ItemStatus::Purchased
+        },
+        fulfillment: Default::default(),
+        product: product_ref(&row.asin),
+    };
+    for _ in 0..to_quantity(&row.quantity) {
+        order.items.push(item.clone());
+    }
+}
+
+fn add_digital_row(orders: &mut Vec<Order>, row: DigitalRow) {
+    let ordered_at = match date_in_japan(&row.order_date) {
+        Some(date) => date,
+        None => return,
+    };
+    let details_url = format!(
+        "https://www.amazon.co.jp/gp/digital/your-account/order-summary.html?orderID={}",
+        row.order_id
+    );
+    let order = order_entry(orders, &row.order_id, ordered_at, details_url);
+    // 注文内容ページから読んだ場合と同じ規則で判別できるよう、リンクと行の文言に見立てる
+    let href = if row.asin.starts_with("amzn1.dv.") {
+        format!("https://www.amazon.co.jp/gp/video/detail/{}", row.asin)
+    } else {
+        format!("https://www.amazon.co.jp/dp/{}", row.asin)
+    };
+    let text = [
+        &row.title,
+        &row.product_name,
+        &row.publisher,
+        &row.seller_of_record,
+    ]
+    .map(|field| field.as_str())
+    .join("\n");
+    let item = Log {
+        hash: row.asin.clone(),
+        kind: digital_kind(&href, &text),
+        name: row.title,
+        price: to_yen(&row.our_price) + to_yen(&row.our_price_tax),
+        purchased_at: ordered_at.to_string(),
+        status: ItemStatus::Purchased,
+        fulfillment: Default::default(),
+        product: product_ref(&row.asin),
+    };
+    for _ in 0..to_quantity(&row.original_quantity) {
+        order.items.push(item.clone());
+    }
+}
+
+// 1注文は商品ごとに複数の行になっているので、同じ注文番号の行は1つにまとめる
+fn order_entry<'a>(
+    orders: &'a mut Vec<Order>,
+    order_id: &str,
+    ordered_at: NaiveDate,
+    details_url: String,
+) -> &'a mut Order {
+    match orders.iter().position(|order| order.id == order_id) {
+        Some(i) => &mut orders[i],
+        None => {
+            let mut order = Order::new(order_id.to_string(), ordered_at, details_url);
+            order.source = Source::DataExport;
+            orders.push(order);
+            orders.last_mut().unwrap()
+        }
+    }
+}
+
+// 日時はUTCなので、注文履歴ページと同じく日本時間の日付にする
+fn date_in_japan(text: &str) -> Option<NaiveDate> {
+    let japan = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+    match DateTime::parse_from_rfc3339(text.trim()) {
+        Ok(at) => Some(at.with_timezone(&japan).date_naive()),
+        Err(_) => NaiveDate::parse_from_str(text.get(..10)?, "%Y-%m-%d").ok(),
+    }
+}
+
+// "1,980"や"1980.0"のような表記を円単位に丸める。読めなければ0
+fn to_yen(text: &str) -> i32 {
+    let cleaned: String = text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .collect();
+    cleaned.parse::<f64>().map_or(0, |yen| yen.round() as i32)
+}
+
+fn to_quantity(text: &str) -> i32 {
+    text.trim().parse().unwrap_or(1).max(1)
+}
+
+fn product_ref(asin: &str) -> Option<ProductRef> {
+    if asin.is_empty() {
+        None
+    } else {
+        Some(ProductRef::from_id(asin))
+    }
+}
+
+// "YAMATO(123456789012)"のような表記を配送業者と伝票番号に分ける
+fn split_carrier(text: &str) -> (Option<String>, Option<String>) {
+    match text.split_once('(') {
+        Some((carrier, rest)) => (
+            Some(carrier.trim().to_string()),
+            Some(rest.trim_end_matches(')').trim().to_string()),
+        ),
+        None => (Some(text.trim().to_string()), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_csv, CsvKind};
+    use crate::order::{OrderStatus, Source};
+    use crate::{ItemKind, ItemStatus, ProductRef};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn 注文履歴のcsvを注文ごとにまとめて読めるか確認() {
+        let csv = "\
+Website,Order ID,Order Date,Currency,Unit Price,Unit Price Tax,Shipping Charge,Total Discounts,Total Owed,ASIN,Quantity,Payment Instrument Type,Order Status,Shipment Status,Carrier Name & Tracking Number,Product Name
+Amazon.co.jp,250-1234567-1234567,2021-07-31T16:30:00Z,JPY,1000,100,0,0,2200,B000000001,2,Visa - 1234,Closed,Shipped,YAMATO(123456789012),\"お茶, 500ml\"
+Amazon.co.jp,250-1234567-1234567,2021-07-31T16:30:00Z,JPY,500,50,0,0,550,4873118220,1,Visa - 1234,Closed,Shipped,YAMATO(123456789012),ある本
+Amazon.co.jp,250-7654321-7654321,2021-08-02T01:00:00Z,JPY,300,30,0,0,0,B000000002,1,Visa - 1234,Cancelled,Not Available,Not Available,取り消した商品
+";
+        let mut orders = vec![];
+        read_csv(CsvKind::Retail, csv.as_bytes(), &mut orders).unwrap();
+        assert_eq!(orders.len(), 2);
+
+        let order = &orders[0];
+        assert_eq!(order.source, Source::DataExport);
+        // UTCの7月31日16時半は日本時間の8月1日
+        assert_eq!(
+            order.ordered_at,
+            NaiveDate::from_ymd_opt(2021, 8, 1).unwrap()
+        );
+        assert_eq!(order.items.len(), 3);
+        assert_eq!(order.items[0].price, 1100);
+        assert_eq!(
+            order.items[2].product,
+            Some(ProductRef::Isbn("4873118220".to_string()))
+        );
+        assert_eq!(order.payment.as_ref().unwrap().grand_total, Some(2750));
+        assert_eq!(order.shipments.len(), 1);
+        assert_eq!(order.shipments[0].carrier.as_deref(), Some("YAMATO"));
+        assert!(!order.is_digital());
+
+        let cancelled = &orders[1];
+        assert_eq!(cancelled.status, OrderStatus::Cancelled);
+        assert_eq!(cancelled.items[0].status, ItemStatus::Cancelled);
+    }
+    #[test]
+    fn デジタル注文のcsvを読めるか確認() {
+        let csv = "\
+ASIN,Title,OrderId,OrderDate,OurPrice,OurPriceTax,OriginalQuantity
+B00KINDLE1,ある電子書籍,D01-1234567-1234567,2021-08-01T03:00:00Z,1000,100,1
+";
+        let mut orders = vec![];
+        read_csv(CsvKind::Digital, csv.as_bytes(), &mut orders).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert!(orders[0].is_digital());
+        assert_eq!(orders[0].items[0].price, 1100);
+    }
+    #[test]
+    fn デジタル商品の種類を注文内容ページと同じく判別するか確認() {
+        let csv = "\
+ASIN,Title,OrderId,OrderDate,OurPrice,OurPriceTax,OriginalQuantity,ProductName,Publisher,SellerOfRecord
+B00KINDLE1,ある電子書籍,D01-1234567-1234567,2021-08-01T03:00:00Z,1000,100,1,ある電子書籍 Kindle版,ある出版社,Amazon Services International
+amzn1.dv.gti.0000,ある映画,D01-7654321-7654321,2021-08-02T03:00:00Z,400,40,1,ある映画 レンタル,,Amazon
+B00GAME001,あるゲーム,D01-1111111-1111111,2021-08-03T03:00:00Z,100,10,1,あるゲーム,,Amazon Appstore
+";
+        let mut orders = vec![];
+        read_csv(CsvKind::Digital, csv.as_bytes(), &mut orders).unwrap();
+        assert_eq!(orders[0].items[0].kind, ItemKind::Kindle);
+        assert_eq!(orders[1].items[0].kind, ItemKind::VideoRental);
+        assert_eq!(orders[2].items[0].kind, ItemKind::App);
+    }
+}
@@ -0,0 +1,502 @@
+use crate::extraction::Completion;
+use crate::invoice::{invoice_url, parse_invoice, save_pdf};
+use crate::order::{Order, OrderStatus};
+use crate::product::ProductRef;
+use crate::utils::{parse_yen, wait_ready};
+use crate::{
+    selector, AmazonBrowser, ExtractOptions, Fulfillment, ItemKind, ItemStatus, Log, Payment,
+};
+use chrono::NaiveDate;
+use regex::Regex;
+use thirtyfour::prelude::*;
+
+impl AmazonBrowser {
+    /// 各注文の注文内容ページをURLで直接開いて商品を読み込む
+    /// `detail_concurrency`が2以上なら、その数のタブで同時に読み込ませてから順に解析する
+    /// キャッシュにある注文はページを開かない
+    /// 中断を求められた場合は1注文ごとに確認して止め、その理由を返す
+    pub(crate) async fn scrape_details(
+        &mut self,
+        orders: &mut Vec<Order>,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Option<Completion>> {
+        let cache = options.cache.as_ref();
+        let mut pending = vec![];
+        for order in orders.iter_mut() {
+            match cache
+                .and_then(|cache| cache.get(&order.id))
+                .filter(|cached| has_requested_data(cached, options))
+            {
+                Some(cached) => {
+                    println!("キャッシュから読み込み: {}", order.id);
+                    // 注文履歴から読んだ状態の方が新しい。非表示にしたかどうかもキャッシュの後で変わりうる
+                    *order = Order {
+                        status: order.status,
+                        shipments: std::mem::take(&mut order.shipments),
+                        archived: order.archived,
+                        ..cached
+                    };
+                }
+                None => pending.push(order),
+            }
+        }
+
+        let driver = self.driver()?;
+        let mut interruption = None;
+        let mut unread = vec![];
+        if options.detail_concurrency <= 1 {
+            for order in pending {
+                if interruption.is_none() {
+                    interruption = self.open_with(&order.details_url, options).await?;
+                }
+                if interruption.is_none() {
+                    interruption = self.read_order(order, options).await?;
+                }
+                if interruption.is_some() {
+                    unread.push(order.details_url.clone());
+                }
+            }
+        } else {
+            let main_window = driver.current_window_handle().await?;
+            for chunk in pending.chunks_mut(options.detail_concurrency) {
+                interruption = interruption.or_else(|| options.interruption());
+                if interruption.is_some() {
+                    unread.extend(chunk.iter().map(|order| order.details_url.clone()));
+                    continue;
+                }
+                let mut tabs = vec![];
+                for order in chunk.iter_mut() {
+                    if interruption.is_none() {
+                        interruption = self.throttle_with(options).await;
+                    }
+                    if interruption.is_some() {
+                        unread.push(order.details_url.clone());
+                        continue;
+                    }
+                    let before = driver.window_handles().await?;
+                    let script = format!(
+                        "window.open({}, '_blank');",
+                        serde_json::json!(order.details_url)
+                    );
+                    driver.execute_script(&script).await?;
+                    let tab = driver
+                        .window_handles()
+                        .await?
+                        .into_iter()
+                        .find(|handle| !before.contains(handle))
+                        .ok_or_else(|| {
+                            WebDriverError::CustomError(format!(
+                                "注文内容のタブを開けませんでした: {}",
+                                order.id
+                            ))
+                        })?;
+                    tabs.push((tab, order));
+                }
+                for (tab, order) in tabs {
+                    driver.switch_to().window(&tab).await?;
+                    interruption = interruption.or_else(|| options.interruption());
+                    if interruption.is_none() {
+                        wait_ready(driver, self.wait_timeout).await?;
+                        interruption = self.read_order(order, options).await?;
+                    }
+                    if interruption.is_some() {
+                        unread.push(order.details_url.clone());
+                    }
+                    driver.close().await?;
+                }
+                driver.switch_to().window(&main_window).await?;
+            }
+        }
+
+        // 中断した場合、注文内容を読めなかった注文は結果に含めない
+        orders.retain(|order| !unread.contains(&order.details_url));
+        Ok(interruption)
+    }
+    // 開いている注文内容ページを読み、必要なら同じタブで領収書も開いてから保存する
+    // 領収書を開く前に中断された場合は保存せずに理由を返す
+    async fn read_order(
+        &self,
+        order: &mut Order,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Option<Completion>> {
+        let driver = self.driver()?;
+        read_details(driver, order).await?;
+        let html = match &options.cache {
+            Some(cache) if cache.keep_html => Some(driver.page_source().await?),
+            _ => None,
+        };
+        let wants_invoice = options.read_invoice || options.invoice_pdf_dir.is_some();
+        if wants_invoice && !order.id.is_empty() {
+            // デジタル注文は注文内容ページがそのまま領収書になっている
+            if !order.is_digital() {
+                let url = invoice_url(&order.id);
+                if let Some(interruption) = self.open_with(&url, options).await? {
+                    return Ok(Some(interruption));
+                }
+            }
+            if options.read_invoice {
+                let text = driver.find_element(selector::BODY).await?.text().await?;
+                let (tax, invoice_numbers) = parse_invoice(&text);
+                order.tax = tax;
+                order.invoice_numbers = invoice_numbers;
+                order.invoice_read = true;
+            }
+            if let Some(dir) = &options.invoice_pdf_dir {
+                order.invoice_pdf = Some(save_pdf(driver, dir, order.ordered_at, &order.id).await?);
+            }
+        }
+        if let Some(cache) = &options.cache {
+            cache.put(order, html.as_deref())?;
+        }
+        Ok(None)
+    }
+}
+
+// 領収書を読まずにキャッシュされた注文は、領収書を求められたら読み直す
+// PDFも保存先を指定されたのに保存されていなければ保存し直す
+fn has_requested_data(cached: &Order, options: &ExtractOptions) -> bool {
+    let invoice = !options.read_invoice || cached.invoice_read;
+    let pdf = options.invoice_pdf_dir.is_none()
+        || cached
+            .invoice_pdf
+            .as_ref()
+            .is_some_and(|path| path.exists());
+    invoice && pdf
+}
+
+async fn read_details(driver: &WebDriver, order: &mut Order) -> WebDriverResult<()> {
+    if order.is_digital() {
+        order.items = parse_digital_items(driver, order.ordered_at).await?;
+    } else {
+        order.items = parse_items(driver, order.ordered_at).await?;
+        order.payment = parse_payment(driver).await?;
+    }
+    if order.status == OrderStatus::Cancelled {
+        for item in &mut order.items {
+            item.status = ItemStatus::Cancelled;
+        }
+    }
+    Ok(())
+}
+
+// 注文内容ページに表示されている商品を読む
+async fn parse_items(driver: &WebDriver, purchased_at: NaiveDate) -> WebDriverResult<Vec<Log>> {
+    let mut result = vec![];
+    let log_elements = driver.find_elements(selector::ITEM).await?;
+
+    // TODO: ×2個以上の場合に対応していないので要注意
+    for log_element in &log_elements {
+        let countability = log_element.find_element(selector::ITEM_QUANTITY).await;
+        let count: i32 = match countability {
+            Ok(e) => e.text().await?.trim().parse().unwrap_or(1),
+            _ => 1,
+        };
+        // ギフト包装やサービスなど、商品ページへのリンクがない行もある
+        let name_column = log_element.find_element(selector::ITEM_NAME_COLUMN).await?;
+        let (name, product) = match name_column.find_element(selector::LINK).await {
+            Ok(link) => (
+                link.text().await?,
+                link.get_attribute("href")
+                    .await?
+                    .and_then(|href| ProductRef::parse(&href)),
+            ),
+            _ => {
+                let text = name_column.text().await?;
+                (text.lines().next().unwrap_or("").trim().to_string(), None)
+            }
+        };
+        let hash = product
+            .as_ref()
+            .map(|product| product.id().to_string())
+            .unwrap_or_default();
+        // キャンセルされた商品は価格が表示されないことがある
+        let price = match log_element.find_element(selector::ITEM_PRICE).await {
+            Ok(e) => parse_yen(&e.text().await?).unwrap_or(0),
+            _ => 0,
+        };
+        // 発送の枠全体ではなく商品の行の文言で判別する。同じ発送の他の商品を巻き込まない
+        let row_text = log_element.text().await?;
+        let status = item_status_from_text(&row_text);
+        let mut fulfillment = fulfillment_from_text(&row_text);
+        if let Ok(e) = log_element.find_element(selector::ITEM_SELLER_LINK).await {
+            fulfillment.seller_id = e
+                .get_attribute("href")
+                .await?
+                .and_then(|href| seller_id_from_url(&href));
+        }
+
+        let new = Log {
+            hash,
+            name,
+            price,
+            purchased_at: purchased_at.to_string(),
+            kind: ItemKind::Physical,
+            status,
+            fulfillment,
+            product,
+        };
+        for _ in 0..count {
+            result.push(new.clone());
+            println!("読み込み完了: {:?}", new);
+        }
+        if status == ItemStatus::Returned {
+            // 返金額が表示されていればその額(一部返金もある)、なければ個数分の価格を返金とする
+            let refunds = match refund_amount_from_text(&row_text) {
+                Some(amount) => vec![-amount],
+                None => vec![-price; count.max(0) as usize],
+            };
+            for amount in refunds {
+                let refund = Log {
+                    price: amount,
+                    status: ItemStatus::Refund,
+                    ..new.clone()
+                };
+                result.push(refund.clone());
+                println!("読み込み完了: {:?}", refund);
+            }
+        }
+    }
+    Ok(result)
+}
+
+// 「注文の概要」が見つからなければNone
+async fn parse_payment(driver: &WebDriver) -> WebDriverResult<Option<Payment>> {
+    let mut lines = vec![];
+    for line in driver.find_elements(selector::PAYMENT_LINE).await? {
+        lines.push(line.text().await?);
+    }
+    if lines.is_empty() {
+        return Ok(None);
+    }
+    let mut payment = Payment::from_lines(&lines);
+    payment.method = match driver.find_element(selector::PAYMENT_METHOD).await {
+        Ok(e) => Some(e.text().await?.trim().to_string()),
+        _ => None,
+    };
+    Ok(Some(payment))
+}
+
+// デジタル注文の注文内容ページは表組みで、商品ごとの数量表記はない
+async fn parse_digital_items(
+    driver: &WebDriver,
+    purchased_at: NaiveDate,
+) -> WebDriverResult<Vec<Log>> {
+    let mut result = vec![];
+    for row in driver.find_elements(selector::DIGITAL_ITEM).await? {
+        let link = row.find_element(selector::DIGITAL_ITEM_LINK).await?;
+        let href = link.get_attribute("href").await?.unwrap_or_default();
+        let name = link.text().await?;
+        let row_text = row.text().await?;
+        let price_text = row
+            .find_element(selector::DIGITAL_ITEM_PRICE)
+            .await?
+            .text()
+            .await?;
+        let product = ProductRef::parse(&href);
+        let hash = product
+            .as_ref()
+            .map(|product| product.id().to_string())
+            .unwrap_or_default();
+
+        let new = Log {
+            hash,
+            name,
+            price: parse_yen(&price_text).unwrap_or(0),
+            purchased_at: purchased_at.to_string(),
+            kind: digital_kind(&href, &row_text),
+            status: ItemStatus::Purchased,
+            fulfillment: fulfillment_from_text(&row_text),
+            product,
+        };
+        result.push(new.clone());
+        println!("読み込み完了: {:?}", new);
+    }
+    Ok(result)
+}
+
+// 「販売: 〇〇」「出荷元: 〇〇」の行から読む
+fn fulfillment_from_text(text: &str) -> Fulfillment {
+    let sold_by = Regex::new(r"(?m)^\s*(?:販売|Sold by)\s*[:：]\s*(.+?)\s*$").unwrap();
+    let shipped_by =
+        Regex::new(r"(?m)^\s*(?:出荷元|発送元|Shipped by)\s*[:：]\s*(.+?)\s*$").unwrap();
+    Fulfillment {
+        sold_by: sold_by.captures(text).map(|caps| caps[1].to_string()),
+        seller_id: None,
+        shipped_by: shipped_by.captures(text).map(|caps| caps[1].to_string()),
+    }
+}
+
+fn seller_id_from_url(url: &str) -> Option<String> {
+    let re = Regex::new(r"[?&]seller=([0-9A-Z]+)").unwrap();
+    re.captures(url).map(|caps| caps[1].to_string())
+}
+
+// 「返金額: ￥ 1,980」から返金された額を正の値で読む
+fn refund_amount_from_text(text: &str) -> Option<i32> {
+    let re = Regex::new(r"(?:返金額|Refund(?:ed)? amount)\s*[:：]?\s*(.+)").unwrap();
+    re.captures(text)
+        .and_then(|caps| parse_yen(&caps[1]))
+        .map(i32::abs)
+}
+
+// 商品の行に表示されている文言から判別する
+fn item_status_from_text(text: &str) -> ItemStatus {
+    // 通常の商品の行にも「返品期間: …まで」や「商品の返品」ボタンがあるので、状況を表す文言だけを見る
+    let is = |phrases: &[&str]| phrases.iter().any(|phrase| text.contains(phrase));
+    if is(&["返金済み", "返金が完了", "返品完了"]) {
+        ItemStatus::Returned
+    } else if is(&[
+        "返品の手続き中",
+        "返品を受け付けました",
+        "返品リクエスト済み",
+    ]) {
+        ItemStatus::ReturnRequested
+    } else if is(&["キャンセルされました", "キャンセル済み"]) {
+        ItemStatus::Cancelled
+    } else {
+        ItemStatus::Purchased
+    }
+}
+
+// データのリクエストのCSVから読む場合にも使う
+pub(crate) fn digital_kind(href: &str, row_text: &str) -> ItemKind {
+    if href.contains("/gp/video/") || row_text.contains("Prime Video") {
+        if row_text.contains("レンタル") {
+            ItemKind::VideoRental
+        } else {
+            ItemKind::VideoPurchase
+        }
+    } else if href.contains("/gp/mas/")
+        || row_text.contains("Appstore")
+        || row_text.contains("アプリ")
+    {
+        ItemKind::App
+    } else if row_text.contains("Kindle") {
+        ItemKind::Kindle
+    } else {
+        ItemKind::OtherDigital
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        digital_kind, fulfillment_from_text, has_requested_data, item_status_from_text,
+        refund_amount_from_text, seller_id_from_url,
+    };
+    use crate::order::Order;
+    use crate::{ExtractOptions, ItemKind, ItemStatus};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn デジタル注文の種類を判別できるか確認() {
+        assert_eq!(
+            digital_kind(
+                "https://www.amazon.co.jp/dp/B07XXXXXXX",
+                "ある本 Kindle版 販売: Amazon Services International, Inc. ￥ 1,100"
+            ),
+            ItemKind::Kindle
+        );
+        assert_eq!(
+            digital_kind(
+                "https://www.amazon.co.jp/gp/video/detail/B08XXXXXXX",
+                "ある映画 (字幕版) レンタル ￥ 400"
+            ),
+            ItemKind::VideoRental
+        );
+        assert_eq!(
+            digital_kind(
+                "https://www.amazon.co.jp/gp/video/detail/B08XXXXXXX",
+                "ある映画 (字幕版) ￥ 2,500"
+            ),
+            ItemKind::VideoPurchase
+        );
+        assert_eq!(
+            digital_kind(
+                "https://www.amazon.co.jp/gp/mas/dl/android/B00XXXXXXX",
+                "あるゲーム ￥ 120"
+            ),
+            ItemKind::App
+        );
+    }
+    #[test]
+    fn 販売元と出荷元を読めるか確認() {
+        let fulfillment = fulfillment_from_text(
+            "USBケーブル 1m\n販売: ○○ストア\n出荷元: Amazon\nコンディション: 新品\n￥ 980",
+        );
+        assert_eq!(fulfillment.sold_by.as_deref(), Some("○○ストア"));
+        assert_eq!(fulfillment.shipped_by.as_deref(), Some("Amazon"));
+        assert!(!fulfillment.is_sold_by_amazon());
+        assert!(fulfillment.is_shipped_by_amazon());
+
+        let fulfillment = fulfillment_from_text("ある本\n販売： アマゾンジャパン合同会社");
+        assert!(fulfillment.is_sold_by_amazon());
+        assert_eq!(fulfillment.shipped_by, None);
+
+        assert_eq!(
+            seller_id_from_url("https://www.amazon.co.jp/gp/help/seller/at-a-glance.html/ref=?ie=UTF8&seller=A1B2C3D4E5F6G7&isAmazonFulfilled=1"),
+            Some("A1B2C3D4E5F6G7".to_string())
+        );
+    }
+    #[test]
+    fn 発送の状況から返品やキャンセルを判別できるか確認() {
+        assert_eq!(
+            item_status_from_text("返金済み 返金額: ￥ 1,980"),
+            ItemStatus::Returned
+        );
+        assert_eq!(
+            item_status_from_text("返品の手続き中です"),
+            ItemStatus::ReturnRequested
+        );
+        assert_eq!(
+            item_status_from_text("この商品はキャンセルされました"),
+            ItemStatus::Cancelled
+        );
+        assert_eq!(item_status_from_text("配達しました"), ItemStatus::Purchased);
+    }
+    #[test]
+    fn 返品期間や返品ボタンだけでは返品扱いにしないか確認() {
+        assert_eq!(
+            item_status_from_text("お茶 500ml\n返品期間: 2021/09/01まで\n￥ 990"),
+            ItemStatus::Purchased
+        );
+        assert_eq!(
+            item_status_from_text("お茶 500ml\n￥ 990\n再度購入\n商品の返品"),
+            ItemStatus::Purchased
+        );
+        assert_eq!(
+            item_status_from_text("キャンセルの手続き\n配達しました"),
+            ItemStatus::Purchased
+        );
+    }
+    #[test]
+    fn 返金額を読めるか確認() {
+        assert_eq!(
+            refund_amount_from_text("返金済み 返金額: ￥ 1,980"),
+            Some(1980)
+        );
+        assert_eq!(
+            refund_amount_from_text("返金済み\n返金額：￥ 500\n"),
+            Some(500)
+        );
+        assert_eq!(refund_amount_from_text("返金済み"), None);
+    }
+    #[test]
+    fn 税率の区分も登録番号もない領収書は読み直さないか確認() {
+        let options = ExtractOptions {
+            read_invoice: true,
+            ..ExtractOptions::default()
+        };
+        let mut cached = Order::new(
+            "250-1".to_string(),
+            NaiveDate::from_ymd_opt(2018, 8, 1).unwrap(),
+            String::new(),
+        );
+        assert!(!has_requested_data(&cached, &options));
+        // 古い注文の領収書には税率の区分も登録番号もない
+        cached.invoice_read = true;
+        assert_eq!(cached.tax, None);
+        assert!(cached.invoice_numbers.is_empty());
+        assert!(has_requested_data(&cached, &options));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::order::is_digital_url;
+use crate::selector::{self, Entry};
+use crate::AmazonBrowser;
+use std::fmt;
+use thirtyfour::prelude::*;
+
+const SAMPLE_LEN: usize = 40;
+
+#[derive(Debug, Clone)]
+pub struct SelectorCheck {
+    pub page: &'static str,
+    pub name: &'static str,
+    pub required: bool,
+    pub count: usize,
+    pub sample: Option<String>,
+}
+
+impl SelectorCheck {
+    pub fn is_found(&self) -> bool {
+        self.count > 0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SelfCheckReport {
+    pub checks: Vec<SelectorCheck>,
+    pub skipped_pages: Vec<&'static str>,
+}
+
+impl SelfCheckReport {
+    pub fn missing(&self) -> Vec<&SelectorCheck> {
+        self.checks
+            .iter()
+            .filter(|check| check.required && !check.is_found())
+            .collect()
+    }
+    pub fn is_healthy(&self) -> bool {
+        self.missing().is_empty() && self.skipped_pages.is_empty()
+    }
+}
+
+impl fmt::Display for SelfCheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let mark = match (check.is_found(), check.required) {
+                (true, _) => "OK",
+                (false, true) => "NG",
+                (false, false) => "--",
+            };
+            writeln!(
+                f,
+                "[{}] {}/{} ({}) {}",
+                mark,
+                check.page,
+                check.name,
+                check.count,
+                check.sample.as_deref().unwrap_or("")
+            )?;
+        }
+        for page in &self.skipped_pages {
+            writeln!(f, "[--] {} は確認できませんでした", page)?;
+        }
+        Ok(())
+    }
+}
+
+impl AmazonBrowser {
+    async fn inspect(
+        &mut self,
+        report: &mut SelfCheckReport,
+        page: &'static str,
+        entries: &[Entry],
+    ) -> WebDriverResult<()> {
+        let driver = self.driver()?;
+        for entry in entries {
+            let elements = driver.find_elements(entry.by.clone()).await?;
+            let sample = match elements.first() {
+                Some(e) => Some(e.text().await?.chars().take(SAMPLE_LEN).collect()),
+                None => None,
+            };
+            report.checks.push(SelectorCheck {
+                page,
+                name: entry.name,
+                required: entry.required,
+                count: elements.len(),
+                sample,
+            });
+        }
+        Ok(())
+    }
+    // 手順の途中で要素が見つからず先に進めなかったことを、見つからなかったセレクタとして記録する
+    fn record_failure(report: &mut SelfCheckReport, page: &'static str, name: &'static str) {
+        report.checks.push(SelectorCheck {
+            page,
+            name,
+            required: true,
+            count: 0,
+            sample: None,
+        });
+    }
+    // 今開いている注文履歴から、物理商品とデジタルそれぞれ最初の注文内容ページのURLを探す
+    // リンクを辿れない注文は飛ばし、1件でもあれば`history/ORDER_LINKS>LINK`として記録する
+    async fn first_details_urls(
+        &mut self,
+        report: &mut SelfCheckReport,
+    ) -> WebDriverResult<(Option<String>, Option<String>)> {
+        let driver = self.driver()?;
+        let mut physical = None;
+        let mut digital = None;
+        let mut failed = false;
+        for group in driver.find_elements(selector::ORDER_GROUP).await? {
+            let link = match group.find_element(selector::ORDER_LINKS).await {
+                Ok(links) => links.find_element(selector::LINK).await,
+                Err(e) => Err(e),
+            };
+            let url = match link {
+                Ok(link) => link.get_attribute("href").await?,
+                Err(_) => {
+                    failed = true;
+                    continue;
+                }
+            };
+            match url {
+                Some(url) if is_digital_url(&url) => digital = digital.or(Some(url)),
+                Some(url) => physical = physical.or(Some(url)),
+                None => {}
+            }
+        }
+        if failed {
+            AmazonBrowser::record_failure(report, "history", "ORDER_LINKS>LINK");
+        }
+        Ok((physical, digital))
+    }
+    /// サインインしてホーム、注文履歴、注文内容の各ページを巡回し、
+    /// `AmazonBrowser`が使うセレクタがすべて解決できるかを調べる
+    /// セレクタが合わずに先へ進めないページは`skipped_pages`に入れ、エラーにはしない
+    pub async fn self_check(&mut self) -> WebDriverResult<SelfCheckReport> {
+        let mut report = SelfCheckReport::default();
+
+        self.goto_logout().await?;
+        self.goto_login().await?;
+        self.inspect(&mut report, "login", selector::LOGIN).await?;
+
+        if let Err(e) = self.login().await {
+            println!("サインインできませんでした: {}", e);
+            AmazonBrowser::record_failure(&mut report, "login", "sign-in");
+            report
+                .skipped_pages
+                .extend(["home", "history", "details", "digital"]);
+            AmazonBrowser::print_missing(&report);
+            return Ok(report);
+        }
+        self.inspect(&mut report, "home", selector::HOME).await?;
+
+        self.goto_first_history().await?;
+        self.inspect(&mut report, "history", selector::HISTORY)
+            .await?;
+
+        // 直近に該当する注文がなければ注文内容ページは確認できない
+        let (physical, digital) = self.first_details_urls(&mut report).await?;
+        match physical {
+            Some(url) => {
+                self.open(&url).await?;
+                self.inspect(&mut report, "details", selector::DETAILS)
+                    .await?;
+            }
+            None => report.skipped_pages.push("details"),
+        }
+        match digital {
+            Some(url) => {
+                self.open(&url).await?;
+                self.inspect(&mut report, "digital", selector::DIGITAL_DETAILS)
+                    .await?;
+            }
+            None => report.skipped_pages.push("digital"),
+        }
+
+        AmazonBrowser::print_missing(&report);
+        Ok(report)
+    }
+    fn print_missing(report: &SelfCheckReport) {
+        for check in report.missing() {
+            println!("セレクタが見つかりません: {}/{}", check.page, check.name);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::BrowserKind;
+use std::env;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use thirtyfour::prelude::*;
+
+/// 自前で起動したchromedriver/geckodriver
+/// dropされるとプロセスを終了させる
+pub(crate) struct DriverProcess {
+    child: Child,
+    port: u16,
+}
+
+impl DriverProcess {
+    pub(crate) async fn spawn(
+        browser: BrowserKind,
+        binary: Option<&Path>,
+        timeout: Duration,
+    ) -> WebDriverResult<DriverProcess> {
+        let name = match browser {
+            BrowserKind::Chrome => "chromedriver",
+            BrowserKind::Firefox => "geckodriver",
+        };
+        let binary = match binary {
+            Some(binary) => binary.to_path_buf(),
+            None => find_on_path(name).ok_or_else(|| {
+                WebDriverError::CustomError(format!("{} がPATH上に見つかりません", name))
+            })?,
+        };
+        let port = free_port()?;
+        let child = Command::new(&binary)
+            .arg(format!("--port={}", port))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let process = DriverProcess { child, port };
+        process.wait_ready(timeout).await?;
+        println!("{} をポート{}で起動しました。", name, port);
+        Ok(process)
+    }
+    pub(crate) fn url(&self) -> String {
+        format!("http://localhost:{}", self.port)
+    }
+    async fn wait_ready(&self, timeout: Duration) -> WebDriverResult<()> {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
+        let started = Instant::now();
+        loop {
+            let connect = tokio::net::TcpStream::connect(addr);
+            if let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(100), connect).await {
+                break;
+            }
+            if started.elapsed() > timeout {
+                return Err(WebDriverError::Timeout(format!(
+                    "WebDriverがポート{}で応答しません",
+                    self.port
+                )));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DriverProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn find_on_path(name: &str) -> Option<PathBuf> {
+    let file_name = if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    };
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(&file_name))
+            .find(|path| path.is_file())
+    })
+}
+
+fn free_port() -> std::io::Result<u16> {
+    // ポート0でbindするとOSが空いているポートを割り当てる
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
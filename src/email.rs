@@ -0,0 +1,335 @@
+use crate::order::{Order, Source};
+use crate::utils::parse_yen;
+use crate::{ItemKind, ItemStatus, Log, Payment, Shipment, ShipmentStatus};
+use chrono::{FixedOffset, NaiveDate, TimeZone, Utc};
+use mailparse::{MailHeaderMap, ParsedMail};
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Amazonからの注文確認メール、発送のお知らせメールから注文を読む
+/// `.eml`ファイル、mbox形式のファイル、`.eml`の入ったディレクトリを受け付ける
+/// 同じ注文のメールが複数あれば1つの注文にまとめ、注文日の降順で返す
+pub fn import_emails<P: AsRef<Path>>(path: P) -> io::Result<Vec<Order>> {
+    let path = path.as_ref();
+    let mut messages = vec![];
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            if entry
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+            {
+                messages.push(fs::read(entry)?);
+            }
+        }
+    } else {
+        let content = fs::read(path)?;
+        if content.starts_with(b"From ") {
+            messages.extend(split_mbox(&content));
+        } else {
+            messages.push(content);
+        }
+    }
+
+    let mut orders: Vec<Order> = vec![];
+    for message in &messages {
+        let mail = match mailparse::parse_mail(message) {
+            Ok(mail) => mail,
+            Err(_) => continue,
+        };
+        if let Some(order) = order_from_mail(&mail) {
+            merge_mail_order(&mut orders, order);
+        }
+    }
+    orders.sort_by(|a, b| b.ordered_at.cmp(&a.ordered_at));
+    Ok(orders)
+}
+
+// 行頭の"From "で区切る。本文中の">From "は元に戻す
+fn split_mbox(content: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = vec![];
+    let mut current: Option<Vec<u8>> = None;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b"From ") {
+            messages.extend(current.take());
+            current = Some(vec![]);
+        } else if let Some(message) = current.as_mut() {
+            let line = if line.starts_with(b">From ") {
+                &line[1..]
+            } else {
+                line
+            };
+            message.extend_from_slice(line);
+        }
+    }
+    messages.extend(current);
+    messages
+}
+
+fn order_from_mail(mail: &ParsedMail) -> Option<Order> {
+    let from = mail.headers.get_first_value("From").unwrap_or_default();
+    if !from.to_lowercase().contains("amazon") {
+        return None;
+    }
+    let subject = mail.headers.get_first_value("Subject").unwrap_or_default();
+    let sent_at = mail
+        .headers
+        .get_first_value("Date")
+        .and_then(|date| mailparse::dateparse(&date).ok())
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .map(|at| {
+            at.with_timezone(&FixedOffset::east_opt(9 * 60 * 60).unwrap())
+                .date_naive()
+        });
+    let body = body_text(mail)?;
+    parse_order_mail(&subject, &body, sent_at)
+}
+
+// 本文はテキストのパートを優先し、HTMLしかなければタグを除いて使う
+fn body_text(mail: &ParsedMail) -> Option<String> {
+    fn find(mail: &ParsedMail, mimetype: &str) -> Option<String> {
+        if mail.subparts.is_empty() {
+            if mail.ctype.mimetype == mimetype {
+                return mail.get_body().ok();
+            }
+            return None;
+        }
+        mail.subparts.iter().find_map(|part| find(part, mimetype))
+    }
+    find(mail, "text/plain").or_else(|| find(mail, "text/html").map(|html| strip_html(&html)))
+}
+
+fn strip_html(html: &str) -> String {
+    let breaks = Regex::new(r"(?i)<br\s*/?>|</(?:p|div|tr|li|h\d)>").unwrap();
+    let tags = Regex::new(r"<[^>]*>").unwrap();
+    let text = breaks.replace_all(html, "\n");
+    let text = tags.replace_all(&text, "");
+    text.replace("&nbsp;", " ")
+        .replace("&yen;", "¥")
+        .replace("&#165;", "¥")
+        .replace("&amp;", "&")
+}
+
+/// 件名と本文から注文を読む。注文番号がなければNone
+/// 注文日は本文の「注文日」、なければメールの送信日とする
+pub(crate) fn parse_order_mail(
+    subject: &str,
+    body: &str,
+    sent_at: Option<NaiveDate>,
+) -> Option<Order> {
+    let id_re = Regex::new(r"(?:^|[^\d])((?:\d{3}|D\d{2})-\d{7}-\d{7})").unwrap();
+    let id = id_re
+        .captures(subject)
+        .or_else(|| id_re.captures(body))
+        .map(|caps| caps[1].to_string())?;
+    let ordered_at = ordered_at_in_body(body).or(sent_at)?;
+
+    let lines: Vec<&str> = body
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let quantity = Regex::new(r"^(?:数量|Quantity)\s*[:：]\s*(\d+)").unwrap();
+    let mut items = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let count: i32 = match quantity.captures(line) {
+            Some(caps) => caps[1].parse().unwrap_or(1),
+            None => continue,
+        };
+        // 商品名は数量の直前の行、価格は直後の数行のうち金額のある行
+        let name = match i.checked_sub(1).map(|j| lines[j]) {
+            Some(name) if parse_yen(name).is_none() => name.to_string(),
+            _ => continue,
+        };
+        // 表示されているのは数量分の合計なので1個ずつに割る。割り切れない端数は1個目に載せる
+        let line_total = lines[i + 1..]
+            .iter()
+            .take(2)
+            .find_map(|line| parse_yen(line))
+            .unwrap_or(0);
+        let count = count.max(1);
+        let item = Log {
+            hash: String::new(),
+            name,
+            price: line_total / count,
+            purchased_at: ordered_at.to_string(),
+            kind: ItemKind::Physical,
+            status: ItemStatus::Purchased,
+            fulfillment: Default::default(),
+            product: None,
+        };
+        items.push(Log {
+            price: item.price + line_total % count,
+            ..item.clone()
+        });
+        for _ in 1..count {
+            items.push(item.clone());
+        }
+    }
+
+    let details_url = if id.starts_with('D') {
+        format!(
+            "https://www.amazon.co.jp/gp/digital/your-account/order-summary.html?orderID={}",
+            id
+        )
+    } else {
+        format!(
+            "https://www.amazon.co.jp/gp/your-account/order-details?orderID={}",
+            id
+        )
+    };
+    let mut order = Order::new(id, ordered_at, details_url);
+    order.source = Source::Email;
+    order.items = items;
+    let payment = Payment::from_lines(&lines);
+    if payment != Payment::default() {
+        order.payment = Some(payment);
+    }
+    if subject.contains("発送") || subject.to_lowercase().contains("shipped") {
+        order.shipments.push(Shipment {
+            status: ShipmentStatus::Shipped,
+            delivered_at: None,
+            expected: None,
+            carrier: None,
+            tracking_id: None,
+            tracking_url: None,
+        });
+    }
+    Some(order)
+}
+
+fn ordered_at_in_body(body: &str) -> Option<NaiveDate> {
+    let ja = Regex::new(r"注文日\s*[:：]?\s*(\d{4})年\s*(\d{1,2})月\s*(\d{1,2})日").unwrap();
+    if let Some(caps) = ja.captures(body) {
+        return NaiveDate::from_ymd_opt(
+            caps[1].parse().ok()?,
+            caps[2].parse().ok()?,
+            caps[3].parse().ok()?,
+        );
+    }
+    let en = Regex::new(r"(?:Order Placed|Order Date)\s*:\s*([A-Z][a-z]+ \d{1,2}, \d{4})").unwrap();
+    let caps = en.captures(body)?;
+    NaiveDate::parse_from_str(&caps[1], "%B %d, %Y").ok()
+}
+
+// 注文確認と発送のお知らせは同じ注文番号で届くので、足りない項目だけ補う
+fn merge_mail_order(orders: &mut Vec<Order>, order: Order) {
+    let existing = match orders.iter().position(|o| o.id == order.id) {
+        Some(i) => &mut orders[i],
+        None => {
+            orders.push(order);
+            return;
+        }
+    };
+    if existing.items.is_empty() {
+        existing.items = order.items;
+    }
+    if existing.payment.is_none() {
+        existing.payment = order.payment;
+    }
+    if existing.shipments.is_empty() {
+        existing.shipments = order.shipments;
+    }
+    existing.ordered_at = existing.ordered_at.min(order.ordered_at);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{import_emails, parse_order_mail, split_mbox};
+    use crate::order::Source;
+    use chrono::NaiveDate;
+
+    const CONFIRMATION: &str = "\
+Amazon.co.jpでのご注文ありがとうございます。
+注文番号: 250-1234567-1234567
+注文日: 2021年8月1日
+
+お茶 500ml
+数量: 2
+￥ 1,980
+
+ある本
+数量: 1
+￥ 1,100
+
+商品の小計: ￥ 3,080
+配送料・手数料: ￥ 0
+注文合計: ￥ 3,080
+";
+
+    #[test]
+    fn 日本語の注文確認メールを読めるか確認() {
+        let order = parse_order_mail("Amazon.co.jpでのご注文", CONFIRMATION, None).unwrap();
+        assert_eq!(order.id, "250-1234567-1234567");
+        assert_eq!(
+            order.ordered_at,
+            NaiveDate::from_ymd_opt(2021, 8, 1).unwrap()
+        );
+        assert_eq!(order.source, Source::Email);
+        assert_eq!(order.items.len(), 3);
+        assert_eq!(order.items[0].name, "お茶 500ml");
+        // 数量2で￥1,980なので1個あたり￥990
+        assert_eq!(order.items[0].price, 990);
+        assert_eq!(order.items[1].price, 990);
+        assert_eq!(order.items[2].name, "ある本");
+        let payment = order.payment.unwrap();
+        assert_eq!(
+            Some(order.items.iter().map(|item| item.price).sum::<i32>()),
+            payment.subtotal
+        );
+        assert_eq!(payment.grand_total, Some(3080));
+    }
+    #[test]
+    fn 英語の注文確認メールを読めるか確認() {
+        let body = "\
+Order #250-7654321-7654321
+Order Placed: August 2, 2021
+
+USB cable
+Quantity: 1
+¥980
+
+Order Total: ¥980
+";
+        let sent_at = NaiveDate::from_ymd_opt(2021, 8, 3);
+        let order = parse_order_mail("Your Amazon.co.jp order", body, sent_at).unwrap();
+        assert_eq!(
+            order.ordered_at,
+            NaiveDate::from_ymd_opt(2021, 8, 2).unwrap()
+        );
+        assert_eq!(order.items.len(), 1);
+        assert_eq!(order.items[0].price, 980);
+    }
+    #[test]
+    fn mboxの同じ注文のメールを1つにまとめるか確認() {
+        let mbox = format!(
+            "From amazon@example.com Sun Aug  1 10:00:00 2021\n\
+             From: Amazon.co.jp <auto-confirm@amazon.co.jp>\n\
+             Subject: Amazon.co.jpでのご注文\n\
+             Date: Sun, 1 Aug 2021 10:00:00 +0900\n\
+             Content-Type: text/plain; charset=utf-8\n\
+             \n\
+             {}\n\
+             From amazon@example.com Mon Aug  2 10:00:00 2021\n\
+             From: Amazon.co.jp <shipment-tracking@amazon.co.jp>\n\
+             Subject: Amazon.co.jpでのご注文が発送されました\n\
+             Date: Mon, 2 Aug 2021 10:00:00 +0900\n\
+             Content-Type: text/plain; charset=utf-8\n\
+             \n\
+             注文番号: 250-1234567-1234567\n",
+            CONFIRMATION
+        );
+        assert_eq!(split_mbox(mbox.as_bytes()).len(), 2);
+
+        let path =
+            std::env::temp_dir().join(format!("amazon-log-mail-{}.mbox", std::process::id()));
+        std::fs::write(&path, mbox).unwrap();
+        let orders = import_emails(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].items.len(), 3);
+        assert_eq!(orders[0].shipments.len(), 1);
+    }
+}
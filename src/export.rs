@@ -0,0 +1,159 @@
+use crate::{ItemKind, ItemStatus, Order};
+use serde::Serialize;
+use std::io;
+
+/// 1商品1行で書き出す。注文ごとの値(税率ごとの内訳、登録番号)は同じ注文の行に繰り返す
+pub fn write_items_csv<W: io::Write>(orders: &[Order], writer: W) -> csv::Result<()> {
+    let mut out = csv::Writer::from_writer(writer);
+    for order in orders {
+        let tax = order.tax.clone().unwrap_or_default();
+        let invoice_numbers = order.invoice_numbers.join(" ");
+        for item in &order.items {
+            out.serialize(ItemRow {
+                order_id: &order.id,
+                ordered_at: order.ordered_at.to_string(),
+                asin: item.product.as_ref().and_then(|product| product.asin()),
+                name: &item.name,
+                kind: item.kind,
+                status: item.status,
+                price: item.price,
+                amount: item.amount(),
+                sold_by: item.fulfillment.sold_by.as_deref(),
+                seller_id: item.fulfillment.seller_id.as_deref(),
+                shipped_by: item.fulfillment.shipped_by.as_deref(),
+                reduced_rate_total: tax.reduced_rate_total,
+                reduced_rate_tax: tax.reduced_rate_tax,
+                standard_rate_total: tax.standard_rate_total,
+                standard_rate_tax: tax.standard_rate_tax,
+                invoice_numbers: &invoice_numbers,
+            })?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// 1注文1行で、支払いの内訳と税率ごとの内訳、保存した領収書の場所を書き出す
+pub fn write_orders_csv<W: io::Write>(orders: &[Order], writer: W) -> csv::Result<()> {
+    let mut out = csv::Writer::from_writer(writer);
+    for order in orders {
+        let payment = order.payment.clone().unwrap_or_default();
+        let tax = order.tax.clone().unwrap_or_default();
+        out.serialize(OrderRow {
+            order_id: &order.id,
+            ordered_at: order.ordered_at.to_string(),
+            items: order.items.len(),
+            subtotal: payment.subtotal,
+            shipping: payment.shipping,
+            discount: payment.discount,
+            points: payment.points,
+            gift_card: payment.gift_card,
+            tax: payment.tax,
+            grand_total: payment.grand_total,
+            payment_method: payment.method,
+            reduced_rate_total: tax.reduced_rate_total,
+            reduced_rate_tax: tax.reduced_rate_tax,
+            standard_rate_total: tax.standard_rate_total,
+            standard_rate_tax: tax.standard_rate_tax,
+            invoice_numbers: order.invoice_numbers.join(" "),
+            invoice_pdf: order
+                .invoice_pdf
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        })?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ItemRow<'a> {
+    order_id: &'a str,
+    ordered_at: String,
+    asin: Option<&'a str>,
+    name: &'a str,
+    kind: ItemKind,
+    status: ItemStatus,
+    price: i32,
+    amount: i32,
+    sold_by: Option<&'a str>,
+    seller_id: Option<&'a str>,
+    shipped_by: Option<&'a str>,
+    reduced_rate_total: i32,
+    reduced_rate_tax: i32,
+    standard_rate_total: i32,
+    standard_rate_tax: i32,
+    invoice_numbers: &'a str,
+}
+
+#[derive(Serialize)]
+struct OrderRow<'a> {
+    order_id: &'a str,
+    ordered_at: String,
+    items: usize,
+    subtotal: Option<i32>,
+    shipping: i32,
+    discount: i32,
+    points: i32,
+    gift_card: i32,
+    tax: Option<i32>,
+    grand_total: Option<i32>,
+    payment_method: Option<String>,
+    reduced_rate_total: i32,
+    reduced_rate_tax: i32,
+    standard_rate_total: i32,
+    standard_rate_tax: i32,
+    invoice_numbers: String,
+    invoice_pdf: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_items_csv;
+    use crate::order::Order;
+    use crate::{Fulfillment, ItemKind, ItemStatus, Log, ProductRef, TaxSplit};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn 商品ごとに税率の内訳と登録番号を書き出せるか確認() {
+        let order = Order {
+            items: vec![Log {
+                hash: "B000000000".to_string(),
+                name: "お茶, 500ml".to_string(),
+                price: 1080,
+                purchased_at: "2021-08-01".to_string(),
+                kind: ItemKind::Physical,
+                status: ItemStatus::Purchased,
+                fulfillment: Fulfillment {
+                    sold_by: Some("アマゾンジャパン合同会社".to_string()),
+                    seller_id: None,
+                    shipped_by: None,
+                },
+                product: Some(ProductRef::Asin("B000000000".to_string())),
+            }],
+            tax: Some(TaxSplit {
+                reduced_rate_total: 1080,
+                reduced_rate_tax: 80,
+                ..TaxSplit::default()
+            }),
+            invoice_numbers: vec!["T6040001048017".to_string()],
+            ..Order::new(
+                "250-1234567-1234567".to_string(),
+                NaiveDate::from_ymd_opt(2021, 8, 1).unwrap(),
+                String::new(),
+            )
+        };
+        let mut buffer = vec![];
+        write_items_csv(&[order], &mut buffer).unwrap();
+        let csv = String::from_utf8(buffer).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("order_id,ordered_at,asin,name,kind,status,price,amount,sold_by,seller_id,shipped_by,reduced_rate_total,reduced_rate_tax,standard_rate_total,standard_rate_tax,invoice_numbers")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("250-1234567-1234567,2021-08-01,B000000000,\"お茶, 500ml\",Physical,Purchased,1080,1080,アマゾンジャパン合同会社,,,1080,80,0,0,T6040001048017")
+        );
+    }
+}
@@ -0,0 +1,51 @@
+use crate::completeness::CompletenessReport;
+use crate::order::Order;
+use crate::ExtractOptions;
+use thirtyfour::prelude::*;
+
+/// 読み込みが最後まで終わったかどうか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Finished,
+    /// `ExtractOptions::cancel`で中止された
+    Cancelled,
+    /// `ExtractOptions::deadline`を過ぎた
+    DeadlineExceeded,
+}
+
+/// 中断された場合も、それまでに注文内容まで読み終えた注文は`orders`に入る
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pub orders: Vec<Order>,
+    /// 年ごとにAmazonの表示する注文数と読み込んだ数を比べた結果
+    pub completeness: CompletenessReport,
+    pub completion: Completion,
+}
+
+impl Extraction {
+    // `require_complete`なら、最後まで読んだのに注文数が合わない場合をエラーにする
+    pub(crate) fn new(
+        orders: Vec<Order>,
+        completeness: CompletenessReport,
+        completion: Completion,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Extraction> {
+        if options.require_complete
+            && completion == Completion::Finished
+            && !completeness.is_complete()
+        {
+            return Err(WebDriverError::CustomError(format!(
+                "注文数が合いません\n{}",
+                completeness
+            )));
+        }
+        Ok(Extraction {
+            orders,
+            completeness,
+            completion,
+        })
+    }
+    pub fn is_finished(&self) -> bool {
+        self.completion == Completion::Finished
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{AmazonBrowser, BrowserKind, ExtractOptions, Order};
+use chrono::NaiveDate;
+use range::Range;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thirtyfour::prelude::*;
+use thirtyfour::{ExtensionCommand, RequestMethod};
+
+impl AmazonBrowser {
+    /// 注文番号を指定して領収書を`dir`にPDFで保存する
+    /// Chromeは印刷コマンドをheadlessモードでしか受け付けない
+    pub async fn save_invoice(&mut self, order_id: &str, dir: &Path) -> WebDriverResult<PathBuf> {
+        self.check_printable()?;
+        self.login().await?;
+        self.open(&invoice_url(order_id)).await?;
+        let driver = self.driver()?;
+        let text = driver
+            .find_element(crate::selector::BODY)
+            .await?
+            .text()
+            .await?;
+        let ordered_at = ordered_at_in_invoice(&text).ok_or_else(|| {
+            WebDriverError::CustomError(format!("領収書に注文日が見つかりません: {}", order_id))
+        })?;
+        save_pdf(driver, dir, ordered_at, order_id).await
+    }
+    // サインインや注文の読み込みを始める前に、PDFにできないブラウザなら断る
+    pub(crate) fn check_printable(&self) -> WebDriverResult<()> {
+        if self.browser == BrowserKind::Chrome && !self.headless {
+            return Err(WebDriverError::CustomError(
+                "Chromeで領収書をPDFに保存するにはheadlessで起動してください(BrowserConfig::headless)"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+    /// 期間内の注文を読み込み、それぞれの領収書を`dir`に保存する
+    /// 保存先は各注文の`invoice_pdf`に入る
+    pub async fn save_invoices(
+        &mut self,
+        range: &Range,
+        dir: &Path,
+    ) -> WebDriverResult<Vec<Order>> {
+        let options = ExtractOptions {
+            invoice_pdf_dir: Some(dir.to_path_buf()),
+            ..ExtractOptions::default()
+        };
+        let extraction = self.extract_orders_with(range, &options).await?;
+        Ok(extraction.orders)
+    }
+}
+
+/// 保存するPDFのファイル名。日付順に並ぶよう日付を先にする
+pub(crate) fn pdf_file_name(ordered_at: NaiveDate, order_id: &str) -> String {
+    format!("{}_{}.pdf", ordered_at, order_id)
+}
+
+// 今開いているページをPDFにして保存する
+pub(crate) async fn save_pdf(
+    driver: &WebDriver,
+    dir: &Path,
+    ordered_at: NaiveDate,
+    order_id: &str,
+) -> WebDriverResult<PathBuf> {
+    let value = driver.extension_command(PrintPage).await?;
+    let value = value.get("value").unwrap_or(&value);
+    let encoded = value.as_str().ok_or_else(|| {
+        WebDriverError::CustomError(format!("PDFを受け取れませんでした: {}", order_id))
+    })?;
+    let pdf = base64::decode(encoded)
+        .map_err(|e| WebDriverError::CustomError(format!("PDFを読めませんでした: {}", e)))?;
+    fs::create_dir_all(dir)?;
+    let path = dir.join(pdf_file_name(ordered_at, order_id));
+    fs::write(&path, pdf)?;
+    println!("領収書を保存しました: {}", path.display());
+    Ok(path)
+}
+
+// WebDriverのPrint Pageコマンド(thirtyfourには用意されていない)
+#[derive(Debug)]
+struct PrintPage;
+
+impl ExtensionCommand for PrintPage {
+    fn parameters_json(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "orientation": "portrait", "background": true }))
+    }
+    fn method(&self) -> RequestMethod {
+        RequestMethod::Post
+    }
+    fn endpoint(&self) -> String {
+        String::from("/print")
+    }
+}
+
+fn ordered_at_in_invoice(text: &str) -> Option<NaiveDate> {
+    let re = Regex::new(r"注文日[^\d]*(\d{4})年\s*(\d{1,2})月\s*(\d{1,2})日").unwrap();
+    let caps = re.captures(text)?;
+    NaiveDate::from_ymd_opt(
+        caps[1].parse().ok()?,
+        caps[2].parse().ok()?,
+        caps[3].parse().ok()?,
+    )
+}
+
+/// 領収書/購入明細書ページのURL
+pub(crate) fn invoice_url(order_id: &str) -> String {
+    format!(
+        "https://www.amazon.co.jp/gp/css/summary/print.html?orderID={}",
+        order_id
+    )
+}
+
+/// 税率ごとの対象額(税込)とそのうちの消費税額
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaxSplit {
+    /// 軽減税率8%の対象額
+    pub reduced_rate_total: i32,
+    pub reduced_rate_tax: i32,
+    /// 標準税率10%の対象額
+    pub standard_rate_total: i32,
+    pub standard_rate_tax: i32,
+}
+
+/// 領収書のテキストから、税率ごとの内訳と適格請求書発行事業者の登録番号を読む
+/// 販売元が複数あれば登録番号も複数になる
+pub(crate) fn parse_invoice(text: &str) -> (Option<TaxSplit>, Vec<String>) {
+    let bracket = Regex::new(
+        r"(8|10)\s*[%％]\s*対象[^￥¥\n]*[￥¥]\s*([\d,]+)[^￥¥\n]*消費税[^￥¥\n]*[￥¥]\s*([\d,]+)",
+    )
+    .unwrap();
+    let mut split = None;
+    for caps in bracket.captures_iter(text) {
+        let total: i32 = caps[2].replace(',', "").parse().unwrap_or(0);
+        let tax: i32 = caps[3].replace(',', "").parse().unwrap_or(0);
+        let split = split.get_or_insert_with(TaxSplit::default);
+        if &caps[1] == "8" {
+            split.reduced_rate_total += total;
+            split.reduced_rate_tax += tax;
+        } else {
+            split.standard_rate_total += total;
+            split.standard_rate_tax += tax;
+        }
+    }
+
+    // 14桁以上の数字の一部を拾わないよう後ろも確かめる
+    let registration = Regex::new(r"(T\d{13})(?:\D|$)").unwrap();
+    let mut numbers: Vec<String> = vec![];
+    for caps in registration.captures_iter(text) {
+        if !numbers.iter().any(|n| n == &caps[1]) {
+            numbers.push(caps[1].to_string());
+        }
+    }
+    (split, numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ordered_at_in_invoice, parse_invoice, pdf_file_name, TaxSplit};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn 領収書から税率ごとの内訳と登録番号を読めるか確認() {
+        let text = "販売: アマゾンジャパン合同会社\n\
+                    登録番号: T6040001048017\n\
+                    8%対象: ￥ 1,080 (うち消費税 ￥ 80)\n\
+                    10%対象: ￥ 2,200 (うち消費税 ￥ 200)\n\
+                    販売: 〇〇ストア\n\
+                    登録番号: T1234567890123\n\
+                    10%対象: ￥ 1,100 (うち消費税 ￥ 100)\n\
+                    登録番号: T6040001048017";
+        let (split, numbers) = parse_invoice(text);
+        assert_eq!(
+            split,
+            Some(TaxSplit {
+                reduced_rate_total: 1080,
+                reduced_rate_tax: 80,
+                standard_rate_total: 3300,
+                standard_rate_tax: 300,
+            })
+        );
+        assert_eq!(numbers, vec!["T6040001048017", "T1234567890123"]);
+    }
+    #[test]
+    fn 領収書のファイル名が注文日と注文番号になるか確認() {
+        let ordered_at =
+            ordered_at_in_invoice("注文日: 2021年8月1日\n注文番号: 250-1234567-1234567");
+        assert_eq!(ordered_at, NaiveDate::from_ymd_opt(2021, 8, 1));
+        assert_eq!(
+            pdf_file_name(ordered_at.unwrap(), "250-1234567-1234567"),
+            "2021-08-01_250-1234567-1234567.pdf"
+        );
+    }
+    #[test]
+    fn 内訳のない領収書ではnoneを返すか確認() {
+        let (split, numbers) = parse_invoice("ご請求額: ￥ 1,980");
+        assert_eq!(split, None);
+        assert!(numbers.is_empty());
+    }
+}
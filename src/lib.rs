@@ -1,22 +1,145 @@
+mod cache;
+mod completeness;
+mod config;
+mod data_export;
+mod details;
+mod doctor;
+mod driver_process;
+mod email;
+mod export;
+mod extraction;
+mod invoice;
+mod merge;
+mod order;
+mod payment;
+mod pool;
+mod product;
+mod rate_limit;
+mod selector;
+mod shipment;
+mod statement;
 mod utils;
 
-use crate::utils::{to_default, to_option};
+pub use crate::cache::OrderCache;
+pub use crate::completeness::{CompletenessReport, YearCount};
+pub use crate::config::{BrowserConfig, BrowserKind, ExtractOptions, WebDriverServer};
+pub use crate::data_export::import_data_export;
+pub use crate::doctor::{SelectorCheck, SelfCheckReport};
+pub use crate::email::import_emails;
+pub use crate::export::{write_items_csv, write_orders_csv};
+pub use crate::extraction::{Completion, Extraction};
+pub use crate::invoice::TaxSplit;
+pub use crate::merge::{reconcile, Conflict, MergedOrder};
+pub use crate::order::{Order, OrderStatus, Source};
+pub use crate::payment::Payment;
+pub use crate::pool::AmazonBrowserPool;
+pub use crate::product::ProductRef;
+pub use crate::rate_limit::RateLimit;
+pub use crate::shipment::{Shipment, ShipmentStatus};
+pub use crate::statement::{
+    import_card_statement, match_charges, Charge, ChargeMatch, ChargeReport, StatementFormat,
+};
+pub use tokio_util::sync::CancellationToken;
+
+use crate::completeness::reported_count_from_text;
+use crate::driver_process::DriverProcess;
+use crate::order::{merge_archived, order_id_from_url, order_status_from_text};
+use crate::rate_limit::RateLimiter;
+use crate::shipment::read_shipments;
+use crate::utils::wait_element;
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use thirtyfour::prelude::*;
 
 pub type AmazonBrowserResult<T> = WebDriverResult<T>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Log {
     pub hash: String,
     pub name: String,
     pub price: i32,
     pub purchased_at: String,
+    // 種類を持たない頃に保存したキャッシュも読めるようにする
+    #[serde(default)]
+    pub kind: ItemKind,
+    /// 返金は元の商品と同じ`hash`を持ち、`price`が負の`ItemStatus::Refund`として同じ注文に並ぶ
+    #[serde(default)]
+    pub status: ItemStatus,
+    #[serde(default)]
+    pub fulfillment: Fulfillment,
+    /// 商品ページへのリンクがない行(ギフト包装など)ではNoneで、`hash`は空になる
+    #[serde(default)]
+    pub product: Option<ProductRef>,
+}
+
+impl Log {
+    /// 実際に支払った(返金なら戻ってきた)金額。キャンセルされた商品は0
+    pub fn amount(&self) -> i32 {
+        match self.status {
+            ItemStatus::Cancelled => 0,
+            _ => self.price,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemKind {
+    #[default]
+    Physical,
+    Kindle,
+    App,
+    VideoRental,
+    VideoPurchase,
+    /// 上のどれとも判別できなかったデジタル商品
+    OtherDigital,
+}
+
+/// 商品ごとの販売元と出荷元。表示されていなければNone
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fulfillment {
+    pub sold_by: Option<String>,
+    /// マーケットプレイスの出品者ページのURLにある`seller=`の値
+    pub seller_id: Option<String>,
+    pub shipped_by: Option<String>,
+}
+
+impl Fulfillment {
+    pub fn is_sold_by_amazon(&self) -> bool {
+        self.sold_by.as_deref().is_some_and(is_amazon)
+    }
+    pub fn is_shipped_by_amazon(&self) -> bool {
+        self.shipped_by.as_deref().is_some_and(is_amazon)
+    }
+}
+
+fn is_amazon(name: &str) -> bool {
+    name.contains("Amazon") || name.contains("アマゾン")
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemStatus {
+    #[default]
+    Purchased,
+    Cancelled,
+    /// 返品の手続き中で、まだ返金されていない
+    ReturnRequested,
+    /// 返品して返金済み。返金そのものは別の`Refund`の記録になる
+    Returned,
+    Refund,
 }
 
 pub struct AmazonBrowser {
-    driver: Option<Box<WebDriver>>,
+    driver: Option<WebDriver>,
     email: String,
     password: String,
+    wait_timeout: Duration,
+    driver_process: Option<DriverProcess>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    // 領収書のPDF保存ができるかの判断に使う
+    browser: BrowserKind,
+    headless: bool,
 }
 
 impl AmazonBrowser {
@@ -25,344 +148,520 @@ impl AmazonBrowser {
         password: &str,
         user_data_dir: &str,
     ) -> WebDriverResult<AmazonBrowser> {
-        let caps_args = &format!(
-            r#"--user-data-dir="/Users/a.kano/Library/Application Support/Google/Chrome/{}""#,
-            user_data_dir.to_string()
-        );
-        let mut caps = DesiredCapabilities::chrome();
-        let _ = caps.add_chrome_arg(caps_args);
-        let driver = WebDriver::new("http://localhost:4444", &caps).await?;
+        Self::with_config(email, password, &BrowserConfig::new(user_data_dir)).await
+    }
+    pub async fn with_config(
+        email: &str,
+        password: &str,
+        config: &BrowserConfig,
+    ) -> WebDriverResult<AmazonBrowser> {
+        let (server_url, driver_process) = match &config.server {
+            WebDriverServer::Remote(url) => (url.clone(), None),
+            WebDriverServer::Managed(binary) => {
+                let process =
+                    DriverProcess::spawn(config.browser, binary.as_deref(), config.wait_timeout)
+                        .await?;
+                (process.url(), Some(process))
+            }
+        };
+        let driver = match config.browser {
+            BrowserKind::Chrome => {
+                WebDriver::new(&server_url, &config.chrome_capabilities()?).await?
+            }
+            BrowserKind::Firefox => {
+                WebDriver::new(&server_url, &config.firefox_capabilities()?).await?
+            }
+        };
         Ok(AmazonBrowser {
-            driver: Some(Box::new(driver)),
+            driver: Some(driver),
             email: email.to_string(),
             password: password.to_string(),
+            wait_timeout: config.wait_timeout,
+            driver_process,
+            rate_limiter: config
+                .rate_limit
+                .clone()
+                .map(RateLimiter::new)
+                .map(Mutex::new),
+            browser: config.browser,
+            headless: config.headless,
         })
     }
     pub async fn quit(&mut self) -> WebDriverResult<()> {
-        let driver = self.check_out();
-        driver.quit().await?;
-        Ok(())
+        self.close().await
+    }
+    /// ブラウザのセッションを閉じる。何度呼んでもよい
+    /// 閉じた後の操作はエラーになる
+    pub async fn close(&mut self) -> WebDriverResult<()> {
+        let result = match self.driver.take() {
+            Some(driver) => driver.quit().await,
+            None => Ok(()),
+        };
+        // 自前で起動したWebDriverはセッションを閉じてから止める
+        self.driver_process = None;
+        result
     }
 }
 impl AmazonBrowser {
-    fn check_out(&mut self) -> WebDriver {
-        to_default(&mut self.driver)
+    fn driver(&self) -> WebDriverResult<&WebDriver> {
+        self.driver.as_ref().ok_or_else(|| {
+            WebDriverError::CustomError("ブラウザは既に閉じられています".to_string())
+        })
+    }
+}
+impl AmazonBrowser {
+    fn reserve_page(&self) -> Duration {
+        match &self.rate_limiter {
+            Some(limiter) => limiter
+                .lock()
+                .unwrap()
+                .reserve(Instant::now(), Local::now().naive_local()),
+            None => Duration::ZERO,
+        }
+    }
+    // ページ遷移の前に呼び、レート制限の分だけ待つ
+    async fn throttle(&self) {
+        tokio::time::sleep(self.reserve_page()).await;
+    }
+    // 読み込み中のページ遷移の前に呼ぶ。日付が変わるまで待つこともあるので、
+    // 待っている間にキャンセルされるか期限を過ぎたら待つのをやめて中断の理由を返す
+    async fn throttle_with(&self, options: &ExtractOptions) -> Option<Completion> {
+        if let Some(interruption) = options.interruption() {
+            return Some(interruption);
+        }
+        let cancelled = async {
+            match &options.cancel {
+                Some(cancel) => cancel.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        let deadline = async {
+            match options.deadline {
+                Some(deadline) => {
+                    tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(self.reserve_page()) => None,
+            _ = cancelled => Some(Completion::Cancelled),
+            _ = deadline => Some(Completion::DeadlineExceeded),
+        }
+    }
+    async fn open(&self, url: &str) -> WebDriverResult<()> {
+        self.throttle().await;
+        self.driver()?.get(url).await
     }
-    fn check_in(&mut self, driver: WebDriver) {
-        to_option(&mut self.driver, driver);
+    // 中断された場合はページを開かずに理由を返す
+    async fn open_with(
+        &self,
+        url: &str,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Option<Completion>> {
+        if let Some(interruption) = self.throttle_with(options).await {
+            return Ok(Some(interruption));
+        }
+        self.driver()?.get(url).await?;
+        Ok(None)
+    }
+}
+impl Drop for AmazonBrowser {
+    // closeされずに捨てられた場合もブラウザのセッションを残さないようにする
+    // tokioのランタイム外でdropされた場合はWebDriverプロセスの停止だけ行われる
+    fn drop(&mut self) {
+        if let (Some(driver), Ok(handle)) =
+            (self.driver.take(), tokio::runtime::Handle::try_current())
+        {
+            let driver_process = self.driver_process.take();
+            handle.spawn(async move {
+                let _ = driver.quit().await;
+                drop(driver_process);
+            });
+        }
     }
 }
 
 impl AmazonBrowser {
     async fn title(&mut self) -> WebDriverResult<String> {
-        let driver = self.check_out();
+        let driver = self.driver()?;
         let title = driver.title().await?;
-        self.check_in(driver);
         Ok(title)
     }
     async fn goto_home(&mut self) -> WebDriverResult<()> {
-        let driver = self.check_out();
         let home_url = "https://www.amazon.co.jp/ref=nav_logo";
-        driver.get(home_url).await?;
-        self.check_in(driver);
+        self.open(home_url).await?;
         Ok(())
     }
     async fn goto_login(&mut self) -> WebDriverResult<()> {
-        let driver = self.check_out();
         let login_url = "https://www.amazon.co.jp/ap/signin?ie=UTF8&openid.pape.max_auth_age=0&openid.return_to=https%3A%2F%2Fwww.amazon.co.jp%2Fgp%2Fcss%2Fhomepage.html%3Fref_%3Dnav_youraccount_switchacct&openid.identity=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0%2Fidentifier_select&openid.assoc_handle=jpflex&_encoding=UTF8&openid.mode=checkid_setup&ignoreAuthState=1&openid.claimed_id=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0%2Fidentifier_select&openid.ns=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0";
-        driver.get(login_url).await?;
-        self.check_in(driver);
+        self.open(login_url).await?;
         Ok(())
     }
     async fn goto_logout(&mut self) -> WebDriverResult<()> {
-        let driver = self.check_out();
         let logout_url = "https://www.amazon.co.jp/gp/flex/sign-out.html?path=%2Fgp%2Fyourstore%2Fhome&signIn=1&useRedirectOnSuccess=1&action=sign-out&ref_=nav_AccountFlyout_signout";
-        driver.get(logout_url).await?;
-        self.check_in(driver);
+        self.open(logout_url).await?;
         Ok(())
     }
     async fn login(&mut self) -> WebDriverResult<()> {
         self.goto_logout().await?;
         self.goto_login().await?;
 
-        let driver = self.check_out();
+        let driver = self.driver()?;
 
-        let element_email = driver.find_element(By::Id("ap_email")).await?;
+        let element_email = wait_element(driver, selector::LOGIN_EMAIL, self.wait_timeout).await?;
         element_email.send_keys(&self.email).await?;
-        let element_email_button = driver.find_element(By::Id("continue")).await?;
+        let element_email_button = driver.find_element(selector::LOGIN_EMAIL_BUTTON).await?;
+        self.throttle().await;
         element_email_button.click().await?;
 
-        let element_password = driver.find_element(By::Id("ap_password")).await?;
+        // headlessではクリックがパスワード画面の読み込みを待たずに返る
+        let element_password =
+            wait_element(driver, selector::LOGIN_PASSWORD, self.wait_timeout).await?;
         element_password.send_keys(&self.password).await?;
-        let element_password_button = driver.find_element(By::Id("signInSubmit")).await?;
+        let element_password_button = driver.find_element(selector::LOGIN_PASSWORD_BUTTON).await?;
+        self.throttle().await;
         element_password_button.click().await?;
-
-        self.check_in(driver);
+        wait_element(driver, selector::NAV_MESSAGE, self.wait_timeout).await?;
 
         self.goto_home().await?;
         Ok(())
     }
-    async fn goto_history(&mut self, year: &i32) -> WebDriverResult<()> {
-        let driver = self.check_out();
+    async fn goto_history(
+        &mut self,
+        year: &i32,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Option<Completion>> {
         let history_url = format!("https://www.amazon.co.jp/gp/your-account/order-history?opt=ab&digitalOrders=1&unifiedOrders=1&returnTo=&__mk_ja_JP=%E3%82%AB%E3%82%BF%E3%82%AB%E3%83%8A&orderFilter=year-{}", year);
-        driver.get(history_url).await?;
-        self.check_in(driver);
-        Ok(())
+        if let Some(interruption) = self.open_with(&history_url, options).await? {
+            return Ok(Some(interruption));
+        }
+        wait_element(self.driver()?, selector::YEAR_PROMPT, self.wait_timeout).await?;
+        Ok(None)
+    }
+    // 非表示にした注文は年ごとの一覧に出ず、この一覧にまとめて出る
+    async fn goto_archived_history(
+        &mut self,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Option<Completion>> {
+        let archived_url = "https://www.amazon.co.jp/gp/your-account/order-history?opt=ab&digitalOrders=1&unifiedOrders=1&orderFilter=archived";
+        if let Some(interruption) = self.open_with(archived_url, options).await? {
+            return Ok(Some(interruption));
+        }
+        wait_element(self.driver()?, selector::YEAR_PROMPT, self.wait_timeout).await?;
+        Ok(None)
     }
     async fn nav_message(&mut self) -> WebDriverResult<String> {
-        let driver = self.check_out();
+        let driver = self.driver()?;
         let message = driver
-            .find_element(By::Id("glow-ingress-line1"))
+            .find_element(selector::NAV_MESSAGE)
             .await?
             .text()
             .await?;
-        self.check_in(driver);
         Ok(message)
     }
     async fn year_in_prompt(&mut self) -> WebDriverResult<String> {
-        let driver = self.check_out();
+        let driver = self.driver()?;
         let message = driver
-            .find_element(By::ClassName("a-dropdown-prompt"))
+            .find_element(selector::YEAR_PROMPT)
             .await?
             .text()
             .await?;
-        self.check_in(driver);
         Ok(message)
     }
 }
 
-use async_recursion::async_recursion;
+// 注文履歴の1グループの注文日
+async fn order_date(group: &WebElement<'_>) -> WebDriverResult<NaiveDate> {
+    let text = group
+        .find_element(selector::ORDER_INFO)
+        .await?
+        .find_element(selector::ORDER_DATE)
+        .await?
+        .text()
+        .await?;
+    NaiveDate::parse_from_str(text.trim(), "%Y年%m月%d日")
+        .map_err(|e| WebDriverError::CustomError(format!("注文日を読めません: {} ({})", text, e)))
+}
+
 impl AmazonBrowser {
-    #[async_recursion]
+    // 注文履歴のページを順に辿り、範囲内の注文の番号と注文内容ページのURLを集める
+    // 辿った注文の数は範囲外のものも含めて`count`に数える
     async fn scrape_history(
         &mut self,
-        result: WebDriverResult<Vec<Log>>,
         range: &Range,
-    ) -> WebDriverResult<Vec<Log>> {
+        count: &mut YearCount,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Vec<Order>> {
         use crate::utils::to_naive_date;
-        use chrono::NaiveDate;
-        use regex::Regex;
 
-        let driver = self.check_out();
+        let driver = self.driver()?;
+        let mut orders = vec![];
 
-        let mut result = result.unwrap();
+        loop {
+            let mut purchased_at = to_naive_date(range.end());
+            let groups = driver.find_elements(selector::ORDER_GROUP).await?;
+            for group in &groups {
+                purchased_at = order_date(group).await?;
 
-        let mut purchased_at = to_naive_date(range.end());
-        let groups = driver.find_elements(By::ClassName("a-box-group")).await?;
-        for n in 0..groups.len() {
-            let groups = driver.find_elements(By::ClassName("a-box-group")).await?;
-            let group = groups.get(n).unwrap();
-            let purchased_at_str = group
-                .find_element(By::ClassName("a-span3"))
-                .await?
-                .find_element(By::ClassName("a-color-secondary.value"))
-                .await?
-                .text()
-                .await?;
-            purchased_at = NaiveDate::parse_from_str(&purchased_at_str, "%Y年%m月%d日").unwrap();
-
-            // 降順なので大きいとやり直し
-            if purchased_at > to_naive_date(range.end()) {
-                continue;
+                // 降順なので大きいとやり直し
+                if purchased_at > to_naive_date(range.end()) {
+                    count.visited += 1;
+                    continue;
+                }
+                // 小さいと終了
+                if purchased_at < to_naive_date(range.start()) {
+                    break;
+                }
+                count.visited += 1;
+                let details_url = group
+                    .find_element(selector::ORDER_LINKS)
+                    .await?
+                    .find_element(selector::LINK)
+                    .await?
+                    .get_attribute("href")
+                    .await?;
+                let details_url = match details_url {
+                    Some(url) => url,
+                    None => continue,
+                };
+                let id = order_id_from_url(&details_url).unwrap_or_default();
+                orders.push(Order {
+                    status: order_status_from_text(&group.text().await?),
+                    shipments: read_shipments(group, purchased_at).await?,
+                    ..Order::new(id, purchased_at, details_url)
+                });
             }
+
             // 小さいと終了
             if purchased_at < to_naive_date(range.start()) {
                 break;
             }
-            // 次のコードはページ遷移処理ブロック
-            {
-                group
-                    .find_element(By::ClassName("a-unordered-list"))
-                    .await?
-                    .find_elements(By::ClassName("a-link-normal"))
-                    .await?
-                    .first()
-                    .unwrap()
-                    .click()
-                    .await?; // -> 注文内容を表示ページへ遷移
-                let log_elements = driver
-                    .find_elements(By::ClassName("a-fixed-left-grid-inner"))
-                    .await?;
-
-                // TODO: ×2個以上の場合に対応していないので要注意
-                for log_element in &log_elements {
-                    let countability = log_element
-                        .find_element(By::ClassName("item-view-qty"))
-                        .await;
-                    let count: i32 = match countability {
-                        Ok(e) => e.text().await?.parse().unwrap(),
-                        _ => 1,
-                    };
-                    let href_str = log_element
-                        .find_element(By::ClassName("a-link-normal"))
-                        .await?
-                        .get_attribute("href")
-                        .await?
-                        .unwrap();
-                    let re = Regex::new(r"/gp/product/(\w{10})/ref=").unwrap();
-                    let caps = re.captures(&href_str).unwrap();
-                    let hash = caps.get(1).unwrap().as_str().to_string();
-
-                    let name = log_element
-                        .find_element(By::ClassName("a-col-right"))
-                        .await?
-                        .find_element(By::ClassName("a-link-normal"))
-                        .await?
-                        .text()
-                        .await?;
-                    let price_raw_str = log_element
-                        .find_element(By::ClassName("a-color-price"))
-                        .await?
-                        .text()
-                        .await?;
-                    let price_str: String = price_raw_str.trim().replace(&['￥', ' ', ','][..], "");
-                    let price = price_str.parse::<i32>().unwrap();
-
-                    let new = Log {
-                        hash: hash,
-                        name: name,
-                        price: price,
-                        purchased_at: purchased_at.to_string(),
-                    };
-                    for _ in 0..count {
-                        result.push(new.clone());
-                        println!("読み込み完了: {:?}", new);
-                    }
-                }
-                driver.back().await?;
+            if !self.goto_next_history_page(options).await? {
+                // 中断して次のページを開かなかった場合は最後まで辿っていない
+                count.walked_to_end = options.interruption().is_none();
+                break;
             }
         }
-
-        // 小さいと終了
-        if purchased_at < to_naive_date(range.start()) {
-            self.check_in(driver);
-            return Ok(result);
-        }
-        if let Ok(_) = driver
-            .find_element(By::ClassName("a-disabled.a-last"))
+        Ok(orders)
+    }
+    // 注文履歴の次のページを開く。最後のページか、中断されて開かなかったらfalse
+    async fn goto_next_history_page(&self, options: &ExtractOptions) -> WebDriverResult<bool> {
+        let driver = self.driver()?;
+        if driver
+            .find_element(selector::NEXT_PAGE_DISABLED)
             .await
+            .is_ok()
         {
-            self.check_in(driver);
-        } else if let Ok(e) = driver.find_element(By::ClassName("a-last")).await {
-            e.click().await?;
-            self.check_in(driver);
-            result = self.scrape_history(Ok(result), range).await?
-        } else {
-            self.check_in(driver);
+            return Ok(false);
+        }
+        let e = match driver.find_element(selector::NEXT_PAGE).await {
+            Ok(e) => e,
+            _ => return Ok(false),
+        };
+        // クリックだと遷移完了前に旧ページの要素を拾うことがあるのでURLで直接開く
+        let next_url = e
+            .find_element(selector::ANCHOR)
+            .await?
+            .get_attribute("href")
+            .await?;
+        if self.throttle_with(options).await.is_some() {
+            return Ok(false);
+        }
+        match next_url {
+            Some(url) => driver.get(&url).await?,
+            None => e.click().await?,
         }
-        Ok(result)
+        wait_element(driver, selector::YEAR_PROMPT, self.wait_timeout).await?;
+        Ok(true)
+    }
+    async fn scrape_year(
+        &mut self,
+        year: &i32,
+        range: &Range,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<(Vec<Order>, YearCount, Option<Completion>)> {
+        let count = YearCount {
+            year: *year,
+            reported: None,
+            visited: 0,
+            walked_to_end: false,
+        };
+        if let Some(interruption) = self.goto_history(year, options).await? {
+            return Ok((vec![], count, Some(interruption)));
+        }
+        let reported = match self.driver()?.find_element(selector::ORDER_COUNT).await {
+            Ok(e) => reported_count_from_text(&e.text().await?),
+            _ => None,
+        };
+        let mut count = YearCount { reported, ..count };
+        let mut orders = self.scrape_history(range, &mut count, options).await?;
+        if !options.include_digital {
+            orders.retain(|order| !order.is_digital());
+        }
+        let interruption = self.scrape_details(&mut orders, options).await?;
+        Ok((orders, count, interruption))
+    }
+    async fn scrape_archived(
+        &mut self,
+        range: &Range,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<(Vec<Order>, Option<Completion>)> {
+        if let Some(interruption) = self.goto_archived_history(options).await? {
+            return Ok((vec![], Some(interruption)));
+        }
+        // 非表示にした注文には年ごとの注文数がないので数えた結果は使わない
+        let mut count = YearCount {
+            year: 0,
+            reported: None,
+            visited: 0,
+            walked_to_end: false,
+        };
+        let mut orders = self.scrape_history(range, &mut count, options).await?;
+        if !options.include_digital {
+            orders.retain(|order| !order.is_digital());
+        }
+        for order in &mut orders {
+            order.archived = true;
+        }
+        let interruption = self.scrape_details(&mut orders, options).await?;
+        Ok((orders, interruption))
     }
 }
 
 use range::Range;
 impl AmazonBrowser {
     pub async fn extract(&mut self, range: &Range) -> WebDriverResult<Vec<Log>> {
-        let mut logs = Ok(vec![]);
+        let orders = self.extract_orders(range).await?;
+        Ok(orders.into_iter().flat_map(|order| order.items).collect())
+    }
+    pub async fn extract_orders(&mut self, range: &Range) -> WebDriverResult<Vec<Order>> {
+        let extraction = self
+            .extract_orders_with(range, &ExtractOptions::default())
+            .await?;
+        Ok(extraction.orders)
+    }
+    /// 中断された場合は読み終えた分までを返し、ブラウザを閉じる
+    pub async fn extract_orders_with(
+        &mut self,
+        range: &Range,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Extraction> {
+        let mut orders = vec![];
+        let mut completeness = CompletenessReport::default();
+        let mut completion = Completion::Finished;
         use crate::utils::to_year;
         let end = to_year(range.end());
         let start = to_year(range.start());
         let years = (start..=end).rev().collect::<Vec<i32>>();
+        if options.invoice_pdf_dir.is_some() {
+            self.check_printable()?;
+        }
         self.login().await?;
         self.goto_home().await?; // Amazonは最初だけ例外的に飛ばされるページがある
         println!("読み込みを開始しました。");
         for year in &years {
-            self.goto_history(year).await?;
-            logs = self.scrape_history(logs, range).await;
+            if let Some(interruption) = options.interruption() {
+                completion = interruption;
+                break;
+            }
+            let (orders_of_year, count, interruption) =
+                self.scrape_year(year, range, options).await?;
+            orders.extend(orders_of_year);
+            completeness.years.push(count);
+            if let Some(interruption) = interruption {
+                completion = interruption;
+                break;
+            }
+        }
+        if options.include_archived && completion == Completion::Finished {
+            let (archived, interruption) = self.scrape_archived(range, options).await?;
+            merge_archived(&mut orders, archived);
+            if let Some(interruption) = interruption {
+                completion = interruption;
+            }
         }
-        println!("読み込みが終了しました。");
-        logs
+        if completion == Completion::Finished {
+            println!("読み込みが終了しました。");
+        } else {
+            println!("読み込みを中断しました。");
+            self.close().await?;
+        }
+        Extraction::new(orders, completeness, completion, options)
     }
     async fn goto_first_history(&mut self) -> WebDriverResult<()> {
-        let driver = self.check_out();
         let first_url = "https://www.amazon.co.jp/gp/css/order-history?ref_=nav_orders_first";
-        driver.get(first_url).await?;
-        self.check_in(driver);
+        self.open(first_url).await?;
         Ok(())
     }
-    fn to_year_num_from_str(maybe_year_str: &str) -> i32 {
+    // 「2021年」なら2021、「過去30日間」のように年でない項目はNone
+    fn to_year_num_from_str(maybe_year_str: &str) -> Option<i32> {
         use regex::Regex;
         let re = Regex::new(r"(\d{4})年").unwrap();
-
-        use chrono::prelude::*;
-        use chrono::{Duration, Local};
-        let yesterday = (Local::today() + Duration::days(-1)).naive_local();
-        let yesterday_year: i32 = yesterday.year();
-
-        let result = re
-            .captures(&maybe_year_str)
+        re.captures(maybe_year_str)
             .and_then(|caps| caps.get(1))
-            .and_then(|year| year.as_str().parse::<i32>().ok());
-        result.unwrap_or(yesterday_year)
+            .and_then(|year| year.as_str().parse::<i32>().ok())
     }
-    pub async fn most_formerly_date(&mut self) -> WebDriverResult<String> {
+    /// 注文履歴の年の選択肢にある年(降順)
+    pub async fn offered_years(&mut self) -> WebDriverResult<Vec<i32>> {
         self.login().await?;
         self.goto_first_history().await?;
 
-        let driver = self.check_out();
-
+        let driver = self.driver()?;
+        self.throttle().await;
         driver
-            .find_element(By::Id("a-autoid-1-announce"))
+            .find_element(selector::YEAR_DROPDOWN)
             .await?
             .click()
             .await?;
-        let dropdown_elements = driver
-            .find_elements(By::ClassName("a-dropdown-item"))
-            .await?;
-
-        // assert_eq!(dropdown_elements.len(), 7);
-
-        use futures::prelude::*;
-        let dropdown_ok_strs = stream::iter(&dropdown_elements)
-            .then(|e| e.text())
-            .collect::<Vec<WebDriverResult<String>>>()
-            .await;
-
-        self.check_in(driver);
-
-        let dropdown_strs = dropdown_ok_strs
-            .iter()
-            .map(|ok_str| ok_str.as_ref().unwrap().clone())
-            .collect::<Vec<String>>();
-
-        // assert_eq!(
-        //     dropdown_strs,
-        //     vec![
-        //         "過去30日間",
-        //         "過去3か月",
-        //         "2022年",
-        //         "2021年",
-        //         "2020年",
-        //         "2019年",
-        //         "2018年"
-        //     ]
-        // );
-
-        let all_years: Vec<i32> = dropdown_strs
-            .iter()
-            .map(|maybe_year_str| Self::to_year_num_from_str(maybe_year_str))
-            .collect();
-
-        // assert_eq!(all_years, vec![2022, 2022, 2022, 2021, 2020, 2019, 2018]);
-
-        let most_formerly_year = all_years.iter().min().unwrap();
-        let most_formerly_date = format!("{}-01-01", most_formerly_year);
-        Ok(most_formerly_date)
+        let mut years = vec![];
+        for element in driver.find_elements(selector::YEAR_DROPDOWN_ITEM).await? {
+            if let Some(year) = Self::to_year_num_from_str(&element.text().await?) {
+                years.push(year);
+            }
+        }
+        years.sort_unstable_by(|a, b| b.cmp(a));
+        years.dedup();
+        Ok(years)
+    }
+    /// 最も古い注文の日付と、注文履歴の年の選択肢にある年(降順)
+    /// 最も古い年の最後のページを開き、最後の注文の日付を読む
+    pub async fn most_formerly_date(&mut self) -> WebDriverResult<(NaiveDate, Vec<i32>)> {
+        let years = self.offered_years().await?;
+        let earliest_year = *years.last().ok_or_else(|| {
+            WebDriverError::CustomError("注文履歴に年の選択肢がありません".to_string())
+        })?;
+        let options = ExtractOptions::default();
+        self.goto_history(&earliest_year, &options).await?;
+        while self.goto_next_history_page(&options).await? {}
+
+        let driver = self.driver()?;
+        let groups = driver.find_elements(selector::ORDER_GROUP).await?;
+        let last = groups.last().ok_or_else(|| {
+            WebDriverError::CustomError(format!("{}年の注文が見つかりません", earliest_year))
+        })?;
+        let earliest = order_date(last).await?;
+        Ok((earliest, years))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{AmazonBrowser, Log};
+    use super::{
+        AmazonBrowser, AmazonBrowserPool, BrowserConfig, CancellationToken, Completion,
+        ExtractOptions, Fulfillment, ItemKind, ItemStatus, Log, WebDriverServer,
+    };
+    use chrono::Datelike;
     use range::Range;
     use thirtyfour::prelude::*;
     use tokio;
 
     #[test]
     fn to_year_num_from_strが正しいか確認() {
-        assert_eq!(AmazonBrowser::to_year_num_from_str("過去30日間"), 2022);
-        assert_eq!(AmazonBrowser::to_year_num_from_str("過去3か月"), 2022);
-        assert_eq!(AmazonBrowser::to_year_num_from_str("2022年"), 2022);
-        assert_eq!(AmazonBrowser::to_year_num_from_str("2018年"), 2018);
+        assert_eq!(AmazonBrowser::to_year_num_from_str("過去30日間"), None);
+        assert_eq!(AmazonBrowser::to_year_num_from_str("過去3か月"), None);
+        assert_eq!(AmazonBrowser::to_year_num_from_str("2022年"), Some(2022));
+        assert_eq!(AmazonBrowser::to_year_num_from_str("2018年"), Some(2018));
     }
     #[tokio::test]
     async fn 最初の取引年を取得し正しいか確認() -> WebDriverResult<()> {
@@ -372,8 +671,10 @@ mod tests {
         let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
         let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
         let mut browser = AmazonBrowser::new(&email, &pass, "formerly_year_correct").await?;
-        let most_formerly_date = "2018-01-01";
-        assert_eq!(browser.most_formerly_date().await?, most_formerly_date);
+        let (most_formerly_date, years) = browser.most_formerly_date().await?;
+        assert_eq!(most_formerly_date.year(), 2018);
+        assert_eq!(years.last(), Some(&2018));
+        assert!(years.windows(2).all(|pair| pair[0] > pair[1]));
         browser.quit().await?;
         Ok(())
     }
@@ -394,6 +695,55 @@ mod tests {
         Ok(())
     }
     #[tokio::test]
+    async fn firefoxでもサインイン画面に行けるか() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let config = BrowserConfig::firefox("signin_firefox");
+        let mut browser = AmazonBrowser::with_config(&email, &pass, &config).await?;
+        browser.goto_logout().await?;
+        browser.goto_login().await?;
+        let login_title = "Amazonサインイン";
+        assert_eq!(browser.title().await?, login_title);
+        browser.goto_logout().await?;
+        browser.quit().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn 自前で起動したchromedriverでサインイン画面に行けるか() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let config = BrowserConfig {
+            server: WebDriverServer::Managed(None),
+            ..BrowserConfig::new("signin_managed")
+        };
+        let mut browser = AmazonBrowser::with_config(&email, &pass, &config).await?;
+        browser.goto_logout().await?;
+        browser.goto_login().await?;
+        let login_title = "Amazonサインイン";
+        assert_eq!(browser.title().await?, login_title);
+        browser.quit().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn 閉じた後の操作はpanicせずエラーになるか() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let mut browser = AmazonBrowser::new(&email, &pass, "closed").await?;
+        browser.close().await?;
+        assert!(browser.goto_home().await.is_err());
+        browser.close().await?;
+        Ok(())
+    }
+    #[tokio::test]
     async fn サインインとhome到達チェック() -> WebDriverResult<()> {
         use dotenv::dotenv;
         use std::env;
@@ -438,7 +788,9 @@ mod tests {
         let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
         let mut browser = AmazonBrowser::new(&email, &pass, "history2020").await?;
         browser.login().await?;
-        browser.goto_history(&2020).await?;
+        browser
+            .goto_history(&2020, &ExtractOptions::default())
+            .await?;
         let year_in_prompot = "2020年";
         let prompt_year = browser.year_in_prompt().await?;
         assert_eq!(prompt_year, year_in_prompot);
@@ -461,6 +813,71 @@ mod tests {
         Ok(())
     }
     #[tokio::test]
+    async fn 一年分の注文数がamazonの表示と合うか確認() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let mut browser = AmazonBrowser::new(&email, &pass, "completeness").await?;
+        let span = Range::new("2021-01-01", "2021-12-31");
+        let extraction = browser
+            .extract_orders_with(&span, &ExtractOptions::default())
+            .await?;
+        assert!(extraction.completeness.years[0].walked_to_end);
+        assert!(
+            extraction.completeness.is_complete(),
+            "{}",
+            extraction.completeness
+        );
+        browser.quit().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn 複数ブラウザで手分けしても同じ結果になるか確認() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let config = BrowserConfig::new("pool");
+        let mut pool = AmazonBrowserPool::new(&email, &pass, &config, 2).await?;
+        let span = Range::new("2020-07-17", "2021-10-19");
+        let logs = pool.extract(&span).await?;
+        assert!(logs
+            .windows(2)
+            .all(|pair| pair[0].purchased_at >= pair[1].purchased_at));
+        assert_eq!(
+            logs.iter().filter(|&log| log.hash == "B088KDK163").count(),
+            1
+        );
+        pool.close().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn 電子書籍も含めると増えるか確認() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let mut browser = AmazonBrowser::new(&email, &pass, "digital").await?;
+        let span = Range::new("2021-08-17", "2021-09-18");
+        let options = ExtractOptions {
+            include_digital: true,
+            ..ExtractOptions::default()
+        };
+        let orders = browser.extract_orders_with(&span, &options).await?.orders;
+        let logs = orders
+            .iter()
+            .flat_map(|order| order.items.iter())
+            .collect::<Vec<_>>();
+        assert!(logs.len() > 2);
+        assert!(logs.iter().any(|log| log.kind != ItemKind::Physical));
+        browser.quit().await?;
+        Ok(())
+    }
+    #[tokio::test]
     async fn ギフト商品が読めているか確認するテスト() -> WebDriverResult<()> {
         use dotenv::dotenv;
         use std::env;
@@ -495,6 +912,49 @@ mod tests {
         Ok(())
     }
     #[tokio::test]
+    async fn 注文内容を複数タブで読んでも同じ個数になるか確認() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let mut browser = AmazonBrowser::new(&email, &pass, "tabs").await?;
+        let span = Range::new("2021-10-19", "2021-10-19");
+        let options = ExtractOptions {
+            detail_concurrency: 3,
+            ..ExtractOptions::default()
+        };
+        let orders = browser.extract_orders_with(&span, &options).await?.orders;
+        assert!(orders.iter().all(|order| !order.id.is_empty()));
+        assert_eq!(
+            orders.iter().map(|order| order.items.len()).sum::<usize>(),
+            4
+        );
+        browser.quit().await?;
+        Ok(())
+    }
+    #[tokio::test]
+    async fn キャンセルすると途中までの結果を返してブラウザを閉じるか確認() -> WebDriverResult<()> {
+        use dotenv::dotenv;
+        use std::env;
+        dotenv().ok();
+        let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
+        let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
+        let mut browser = AmazonBrowser::new(&email, &pass, "cancel").await?;
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let options = ExtractOptions {
+            cancel: Some(cancel),
+            ..ExtractOptions::default()
+        };
+        let span = Range::new("2021-10-19", "2021-10-19");
+        let extraction = browser.extract_orders_with(&span, &options).await?;
+        assert_eq!(extraction.completion, Completion::Cancelled);
+        assert!(extraction.orders.is_empty());
+        assert!(browser.goto_home().await.is_err());
+        Ok(())
+    }
+    #[tokio::test]
     async fn nameを問題なく読めているか確認() -> WebDriverResult<()> {
         use dotenv::dotenv;
         use std::env;
@@ -523,6 +983,10 @@ mod tests {
             name: "name".to_string(),
             price: 42,
             purchased_at: "2021-07-17".to_string(),
+            kind: ItemKind::Physical,
+            status: ItemStatus::Purchased,
+            fulfillment: Fulfillment::default(),
+            product: None,
         }];
         assert_eq!(
             logs.iter().filter(|&log| log.hash == "B088KDK163").count(),
@@ -530,16 +994,15 @@ mod tests {
         );
         Ok(())
     }
-    #[ignore]
     #[tokio::test]
-    async fn headlessモードだと通しでextractした場合エラーになるのでやめることにした(
-    ) -> WebDriverResult<()> {
+    async fn headlessモードでも通しでextractできるか確認() -> WebDriverResult<()> {
         use dotenv::dotenv;
         use std::env;
         dotenv().ok();
         let email = env::var("AMAZON_EMAIL").expect("AMAZON_EMAIL must be set");
         let pass = env::var("AMAZON_PASSWORD").expect("AMAZON_PASSWORD must be set");
-        let mut browser = AmazonBrowser::new(&email, &pass, "check_in_bug").await?;
+        let config = BrowserConfig::headless("check_in_bug");
+        let mut browser = AmazonBrowser::with_config(&email, &pass, &config).await?;
         let span = Range::new("2018-01-01", "2022-01-09");
         let logs = browser.extract(&span).await?;
         assert_eq!(logs.len(), 219);
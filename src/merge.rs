@@ -0,0 +1,423 @@
+use crate::order::{Order, Source};
+use crate::{ItemStatus, Log};
+use std::collections::BTreeMap;
+
+/// 複数の取得元から集めた同じ注文を1つにまとめたもの
+#[derive(Debug, Clone)]
+pub struct MergedOrder {
+    /// 項目ごとに最も詳しい取得元の値を選んだ注文
+    pub order: Order,
+    /// 項目名("items"、"payment"など)ごとに、値を採った取得元
+    pub provenance: BTreeMap<&'static str, Source>,
+    /// この注文が見つかった取得元(重複なし、優先順)
+    pub sources: Vec<Source>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl MergedOrder {
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+}
+
+/// 取得元によって値が食い違った項目
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// "ordered_at"や"items[お茶 500ml].price"のような項目名
+    /// 商品が取得元によって欠けている場合は"items[商品名]"で、値は取得元ごとの個数
+    pub field: String,
+    pub values: Vec<(Source, String)>,
+}
+
+/// 注文番号が同じ注文、注文番号がなければ日付が近く同じ商品か同じ金額の注文を同じ注文とみなしてまとめる
+/// 値が同じくらい詳しい場合は注文履歴ページ、データのリクエスト、メールの順に優先する
+pub fn reconcile(orders: Vec<Order>) -> Vec<MergedOrder> {
+    let mut groups: Vec<Vec<Order>> = vec![];
+    for order in orders {
+        match groups
+            .iter_mut()
+            .find(|group| group.iter().any(|other| is_same_order(other, &order)))
+        {
+            Some(group) => group.push(order),
+            None => groups.push(vec![order]),
+        }
+    }
+    let mut merged: Vec<MergedOrder> = groups.into_iter().map(merge_group).collect();
+    merged.sort_by(|a, b| b.order.ordered_at.cmp(&a.order.ordered_at));
+    merged
+}
+
+fn priority(source: Source) -> usize {
+    match source {
+        Source::Scraped => 0,
+        Source::DataExport => 1,
+        Source::Email => 2,
+    }
+}
+
+fn is_same_order(a: &Order, b: &Order) -> bool {
+    if !a.id.is_empty() && !b.id.is_empty() {
+        return a.id == b.id;
+    }
+    let days = (a.ordered_at - b.ordered_at).num_days().abs();
+    if days > 2 {
+        return false;
+    }
+    let shares_item = a
+        .items
+        .iter()
+        .any(|x| b.items.iter().any(|y| is_same_item(x, y)));
+    let total = |order: &Order| order.payment.as_ref().and_then(|p| p.grand_total);
+    shares_item || (total(a).is_some() && total(a) == total(b))
+}
+
+fn asin(item: &Log) -> Option<&str> {
+    item.product.as_ref().and_then(|product| product.asin())
+}
+
+// 両方にASINがあればASINで、どちらかになければ商品名で比べる
+// メールの商品名は途中で切られていることがあるので、片方がもう片方を含んでいれば同じとする
+fn is_same_item(a: &Log, b: &Log) -> bool {
+    if let (Some(x), Some(y)) = (asin(a), asin(b)) {
+        return x == y;
+    }
+    let (x, y) = (normalize_name(&a.name), normalize_name(&b.name));
+    let shorter = x.chars().count().min(y.chars().count());
+    x == y || (shorter >= 4 && (x.contains(&y) || y.contains(&x)))
+}
+
+// 空白を除き、全角の英数字を半角にして小文字にそろえる
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// 取得元をまたいで同じ商品とみなした1行
+struct ItemLine<'a> {
+    item: &'a Log,
+    /// 取得元ごとの個数
+    counts: Vec<(Source, usize)>,
+    /// 取得元ごとの価格(最初に見つかったもの)
+    prices: Vec<(Source, i32)>,
+}
+
+// 返金の記録は注文履歴ページにしかないので突き合わせから外す
+fn item_lines(group: &[Order]) -> Vec<ItemLine<'_>> {
+    let mut lines: Vec<ItemLine> = vec![];
+    for order in group {
+        for item in order
+            .items
+            .iter()
+            .filter(|item| item.status != ItemStatus::Refund)
+        {
+            let i = match lines.iter().position(|line| is_same_item(line.item, item)) {
+                Some(i) => i,
+                None => {
+                    lines.push(ItemLine {
+                        item,
+                        counts: vec![],
+                        prices: vec![],
+                    });
+                    lines.len() - 1
+                }
+            };
+            let line = &mut lines[i];
+            match line
+                .counts
+                .iter_mut()
+                .find(|(source, _)| *source == order.source)
+            {
+                Some((_, count)) => *count += 1,
+                None => {
+                    line.counts.push((order.source, 1));
+                    line.prices.push((order.source, item.price));
+                }
+            }
+        }
+    }
+    lines
+}
+
+fn merge_group(mut group: Vec<Order>) -> MergedOrder {
+    group.sort_by_key(|order| priority(order.source));
+    let mut sources: Vec<Source> = group.iter().map(|order| order.source).collect();
+    sources.dedup();
+    let mut provenance = BTreeMap::new();
+    let mut conflicts = vec![];
+
+    let first = &group[0];
+    let mut order = first.clone();
+    provenance.insert("ordered_at", first.source);
+    provenance.insert("details_url", first.source);
+    provenance.insert("status", first.source);
+
+    // 注文番号は持っている取得元から採る
+    if let Some(with_id) = group.iter().find(|o| !o.id.is_empty()) {
+        order.id = with_id.id.clone();
+        provenance.insert("id", with_id.source);
+    }
+
+    // 商品は最も多く載っている取得元から採る(同数なら優先順)
+    let richest = group
+        .iter()
+        .max_by(|a, b| {
+            a.items
+                .len()
+                .cmp(&b.items.len())
+                .then(priority(b.source).cmp(&priority(a.source)))
+        })
+        .unwrap();
+    order.items = richest.items.clone();
+    provenance.insert("items", richest.source);
+
+    let shipments = group
+        .iter()
+        .max_by(|a, b| {
+            a.shipments
+                .len()
+                .cmp(&b.shipments.len())
+                .then(priority(b.source).cmp(&priority(a.source)))
+        })
+        .unwrap();
+    order.shipments = shipments.shipments.clone();
+    provenance.insert("shipments", shipments.source);
+
+    if let Some(o) = group.iter().find(|o| o.payment.is_some()) {
+        order.payment = o.payment.clone();
+        provenance.insert("payment", o.source);
+    }
+    if let Some(o) = group.iter().find(|o| o.tax.is_some()) {
+        order.tax = o.tax.clone();
+        provenance.insert("tax", o.source);
+    }
+    if let Some(o) = group.iter().find(|o| !o.invoice_numbers.is_empty()) {
+        order.invoice_numbers = o.invoice_numbers.clone();
+        provenance.insert("invoice_numbers", o.source);
+    }
+    if let Some(o) = group.iter().find(|o| o.invoice_pdf.is_some()) {
+        order.invoice_pdf = o.invoice_pdf.clone();
+        provenance.insert("invoice_pdf", o.source);
+    }
+    order.invoice_read = group.iter().any(|o| o.invoice_read);
+    order.archived = group.iter().any(|o| o.archived);
+
+    conflicts.extend(conflict("ordered_at", &group, |o| {
+        Some(o.ordered_at.to_string())
+    }));
+    conflicts.extend(conflict("status", &group, |o| {
+        Some(format!("{:?}", o.status))
+    }));
+    conflicts.extend(conflict("payment.grand_total", &group, |o| {
+        o.payment
+            .as_ref()
+            .and_then(|p| p.grand_total)
+            .map(|total| total.to_string())
+    }));
+    // 商品が1つも載っていない取得元(発送のお知らせメールなど)は欠けているとはみなさない
+    let mut listing: Vec<Source> = group
+        .iter()
+        .filter(|o| !o.items.is_empty())
+        .map(|o| o.source)
+        .collect();
+    listing.dedup();
+    for line in item_lines(&group) {
+        let counts: Vec<(Source, String)> = listing
+            .iter()
+            .map(|&source| {
+                let count = line
+                    .counts
+                    .iter()
+                    .find(|(s, _)| *s == source)
+                    .map_or(0, |(_, count)| *count);
+                (source, count.to_string())
+            })
+            .collect();
+        if counts.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+            conflicts.push(Conflict {
+                field: format!("items[{}]", line.item.name),
+                values: counts,
+            });
+        }
+        if line.prices.windows(2).any(|pair| pair[0].1 != pair[1].1) {
+            conflicts.push(Conflict {
+                field: format!("items[{}].price", line.item.name),
+                values: line
+                    .prices
+                    .iter()
+                    .map(|(source, price)| (*source, price.to_string()))
+                    .collect(),
+            });
+        }
+    }
+
+    MergedOrder {
+        order,
+        provenance,
+        sources,
+        conflicts,
+    }
+}
+
+// 値を持つ取得元の間で1つでも違えば食い違いとする
+fn conflict<F>(field: &str, group: &[Order], value: F) -> Option<Conflict>
+where
+    F: Fn(&Order) -> Option<String>,
+{
+    let values: Vec<(Source, String)> = group
+        .iter()
+        .filter_map(|o| value(o).map(|v| (o.source, v)))
+        .collect();
+    if values.windows(2).all(|pair| pair[0].1 == pair[1].1) {
+        return None;
+    }
+    Some(Conflict {
+        field: field.to_string(),
+        values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reconcile;
+    use crate::order::{Order, Source};
+    use crate::{ItemKind, ItemStatus, Log, Payment, ProductRef};
+    use chrono::NaiveDate;
+
+    fn item(asin: Option<&str>, name: &str, price: i32) -> Log {
+        Log {
+            hash: asin.unwrap_or_default().to_string(),
+            name: name.to_string(),
+            price,
+            purchased_at: "2021-08-01".to_string(),
+            kind: ItemKind::Physical,
+            status: ItemStatus::Purchased,
+            fulfillment: Default::default(),
+            product: asin.map(|asin| ProductRef::Asin(asin.to_string())),
+        }
+    }
+    fn order(id: &str, source: Source, items: Vec<Log>) -> Order {
+        Order {
+            items,
+            source,
+            ..Order::new(
+                id.to_string(),
+                NaiveDate::from_ymd_opt(2021, 8, 1).unwrap(),
+                String::new(),
+            )
+        }
+    }
+
+    #[test]
+    fn 注文番号で突き合わせて詳しい値を採るか確認() {
+        let scraped = order(
+            "250-1",
+            Source::Scraped,
+            vec![item(Some("B000000001"), "お茶 500ml", 990)],
+        );
+        let mut export = order(
+            "250-1",
+            Source::DataExport,
+            vec![
+                item(Some("B000000001"), "お茶 500ml", 990),
+                item(Some("B000000002"), "ある本", 1100),
+            ],
+        );
+        export.payment = Some(Payment {
+            grand_total: Some(2090),
+            ..Payment::default()
+        });
+        let other = order("250-2", Source::Email, vec![item(None, "USBケーブル", 980)]);
+
+        let merged = reconcile(vec![other, export, scraped]);
+        assert_eq!(merged.len(), 2);
+        let merged = merged.iter().find(|m| m.order.id == "250-1").unwrap();
+        assert_eq!(merged.sources, vec![Source::Scraped, Source::DataExport]);
+        assert_eq!(merged.order.items.len(), 2);
+        assert_eq!(merged.provenance["items"], Source::DataExport);
+        assert_eq!(merged.provenance["ordered_at"], Source::Scraped);
+        assert_eq!(merged.provenance["payment"], Source::DataExport);
+        // 注文履歴ページに載っていない商品を名前で挙げる
+        let missing = merged
+            .conflicts
+            .iter()
+            .find(|c| c.field == "items[ある本]")
+            .unwrap();
+        assert_eq!(
+            missing.values,
+            vec![
+                (Source::Scraped, "0".to_string()),
+                (Source::DataExport, "1".to_string())
+            ]
+        );
+        assert_eq!(merged.conflicts.len(), 1);
+    }
+    #[test]
+    fn asinのないメールの商品も名前で突き合わせるか確認() {
+        let scraped = order(
+            "250-1",
+            Source::Scraped,
+            vec![
+                item(Some("B000000001"), "お茶 500ml", 990),
+                item(Some("B000000002"), "ＵＳＢケーブル 1m", 980),
+            ],
+        );
+        // メールには商品名と価格しかない
+        let email = order(
+            "250-1",
+            Source::Email,
+            vec![
+                item(None, "お茶 500ml", 890),
+                item(None, "USBケーブル1m", 980),
+            ],
+        );
+        let merged = reconcile(vec![scraped, email]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].conflicts.len(), 1);
+        let conflict = &merged[0].conflicts[0];
+        assert_eq!(conflict.field, "items[お茶 500ml].price");
+        assert_eq!(
+            conflict.values,
+            vec![
+                (Source::Scraped, "990".to_string()),
+                (Source::Email, "890".to_string())
+            ]
+        );
+
+        let email = order("250-1", Source::Email, vec![item(None, "お茶 500ml", 990)]);
+        let scraped = order(
+            "250-1",
+            Source::Scraped,
+            vec![
+                item(Some("B000000001"), "お茶 500ml", 990),
+                item(Some("B000000002"), "ＵＳＢケーブル 1m", 980),
+            ],
+        );
+        let merged = reconcile(vec![scraped, email]);
+        let missing = &merged[0].conflicts[0];
+        assert_eq!(missing.field, "items[ＵＳＢケーブル 1m]");
+        assert_eq!(missing.values[1], (Source::Email, "0".to_string()));
+    }
+    #[test]
+    fn 注文番号がなくても日付と商品で突き合わせるか確認() {
+        let scraped = order(
+            "",
+            Source::Scraped,
+            vec![item(Some("B000000001"), "お茶 500ml", 990)],
+        );
+        let export = order(
+            "250-1",
+            Source::DataExport,
+            vec![item(Some("B000000001"), "お茶 500ml", 990)],
+        );
+        let merged = reconcile(vec![scraped, export]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].order.id, "250-1");
+        assert!(!merged[0].has_conflicts());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{Log, Payment, Shipment, TaxSplit};
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// 注文履歴の1グループ(1注文)
+/// 履歴ページでは`items`は空で、注文内容ページを読んだ後に埋まる
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    pub id: String,
+    pub ordered_at: NaiveDate,
+    pub details_url: String,
+    pub items: Vec<Log>,
+    #[serde(default)]
+    pub status: OrderStatus,
+    /// 注文履歴に表示されている発送単位ごとの状況
+    #[serde(default)]
+    pub shipments: Vec<Shipment>,
+    /// 注文内容ページを読んだ後に埋まる。デジタル注文では読まない
+    #[serde(default)]
+    pub payment: Option<Payment>,
+    /// 以下は`ExtractOptions::read_invoice`のときだけ領収書から読む
+    #[serde(default)]
+    pub tax: Option<TaxSplit>,
+    /// 適格請求書発行事業者の登録番号(T+13桁)。販売元ごとに1つ
+    #[serde(default)]
+    pub invoice_numbers: Vec<String>,
+    /// 領収書を読んだ。税率の区分も登録番号もない領収書もあるので、読んだかどうかは別に持つ
+    #[serde(default)]
+    pub invoice_read: bool,
+    /// `ExtractOptions::invoice_pdf_dir`に保存した領収書
+    #[serde(default)]
+    pub invoice_pdf: Option<PathBuf>,
+    /// 非表示にした注文の一覧から読んだ
+    #[serde(default)]
+    pub archived: bool,
+    /// どこから読んだ注文か
+    #[serde(default)]
+    pub source: Source,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    /// 注文履歴ページから読んだ
+    #[default]
+    Scraped,
+    /// 「データのリクエスト」でダウンロードしたCSVから読んだ
+    DataExport,
+    /// 注文確認メール、発送のお知らせメールから読んだ
+    Email,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    #[default]
+    Ordered,
+    /// 注文全体がキャンセルされた。商品は`ItemStatus::Cancelled`になる
+    Cancelled,
+}
+
+impl Order {
+    // 注文履歴や取り込んだファイルから分かる最低限の項目だけで作る
+    pub(crate) fn new(id: String, ordered_at: NaiveDate, details_url: String) -> Order {
+        Order {
+            id,
+            ordered_at,
+            details_url,
+            items: vec![],
+            status: OrderStatus::Ordered,
+            shipments: vec![],
+            payment: None,
+            tax: None,
+            invoice_numbers: vec![],
+            invoice_read: false,
+            invoice_pdf: None,
+            archived: false,
+            source: Source::Scraped,
+        }
+    }
+    /// Kindle本やアプリ、Prime Videoなどのデジタル注文
+    pub fn is_digital(&self) -> bool {
+        is_digital_url(&self.details_url)
+    }
+}
+
+// 非表示にした注文を日付の降順を保って加える。通常の一覧にもあった注文は重ねない
+pub(crate) fn merge_archived(orders: &mut Vec<Order>, archived: Vec<Order>) {
+    for order in archived {
+        if !order.id.is_empty() && orders.iter().any(|o| o.id == order.id) {
+            continue;
+        }
+        orders.push(order);
+    }
+    orders.sort_by(|a, b| b.ordered_at.cmp(&a.ordered_at));
+}
+
+// 注文履歴の1グループに表示されている文言から判別する
+pub(crate) fn order_status_from_text(text: &str) -> OrderStatus {
+    if text.contains("キャンセルされました") || text.contains("キャンセル済み") {
+        OrderStatus::Cancelled
+    } else {
+        OrderStatus::Ordered
+    }
+}
+
+pub(crate) fn is_digital_url(details_url: &str) -> bool {
+    details_url.contains("/gp/digital/")
+}
+
+pub(crate) fn order_id_from_url(url: &str) -> Option<String> {
+    let re = Regex::new(r"orderI[Dd]=([0-9A-Z-]+)").unwrap();
+    re.captures(url)
+        .and_then(|caps| caps.get(1))
+        .map(|id| id.as_str().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_archived, order_id_from_url, order_status_from_text, Order, OrderStatus};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn 注文内容ページのurlから注文番号を取り出せるか確認() {
+        assert_eq!(
+            order_id_from_url("https://www.amazon.co.jp/gp/your-account/order-details/ref=ppx_yo_dt_b_order_details_o00?ie=UTF8&orderID=250-1234567-1234567"),
+            Some("250-1234567-1234567".to_string())
+        );
+        assert_eq!(
+            order_id_from_url("https://www.amazon.co.jp/gp/digital/your-account/order-summary.html/ref=ppx_yo_dt_b_dpi_o00?ie=UTF8&orderID=D01-1234567-1234567&print=1"),
+            Some("D01-1234567-1234567".to_string())
+        );
+        assert_eq!(order_id_from_url("https://www.amazon.co.jp/"), None);
+    }
+    #[test]
+    fn キャンセルされた注文を判別できるか確認() {
+        assert_eq!(
+            order_status_from_text(
+                "注文日 2021年8月1日 合計 ￥ 1,980 この注文はキャンセルされました。"
+            ),
+            OrderStatus::Cancelled
+        );
+        assert_eq!(
+            order_status_from_text("注文日 2021年8月1日 合計 ￥ 1,980 配達済み"),
+            OrderStatus::Ordered
+        );
+    }
+    #[test]
+    fn 非表示にした注文を日付順に重ねずに加えるか確認() {
+        let order = |id: &str, day: u32, archived: bool| Order {
+            archived,
+            ..Order::new(
+                id.to_string(),
+                NaiveDate::from_ymd_opt(2021, 8, day).unwrap(),
+                String::new(),
+            )
+        };
+        let mut orders = vec![order("c", 20, false), order("a", 1, false)];
+        merge_archived(&mut orders, vec![order("a", 1, true), order("b", 10, true)]);
+        let ids = orders.iter().map(|o| o.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["c", "b", "a"]);
+        assert!(orders[1].archived);
+        assert!(!orders[2].archived);
+    }
+}
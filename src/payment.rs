@@ -0,0 +1,147 @@
+use crate::utils::parse_yen;
+use serde::{Deserialize, Serialize};
+
+/// 注文内容ページの「注文の概要」に表示される支払いの内訳
+/// 割引やポイントなど差し引かれるものも正の金額で持つ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Payment {
+    pub subtotal: Option<i32>,
+    pub shipping: i32,
+    /// クーポンやプロモーションによる割引の合計
+    pub discount: i32,
+    /// 使ったAmazonポイント
+    pub points: i32,
+    /// 使ったAmazonギフトカードの残高
+    pub gift_card: i32,
+    pub tax: Option<i32>,
+    /// ポイントやギフトカードを差し引いた後、`method`に請求された金額
+    pub grand_total: Option<i32>,
+    /// "Visa ****1234"のような表示のまま持つ
+    pub method: Option<String>,
+}
+
+impl Payment {
+    /// 「商品の小計： ￥ 1,980」のような1行ずつの表示から組み立てる
+    /// 英語のメール("Item Subtotal: ¥1,980")の項目名も読む
+    /// 金額の読めない行や知らない項目は無視する
+    pub(crate) fn from_lines<S: AsRef<str>>(lines: &[S]) -> Payment {
+        let mut payment = Payment::default();
+        let mut order_total = None;
+        for line in lines {
+            let line = line.as_ref();
+            let label = line.split(&['：', ':'][..]).next().unwrap_or("");
+            let amount = match parse_yen(line) {
+                Some(amount) => amount,
+                None => continue,
+            };
+            let is = |words: &[&str]| {
+                let lower = label.to_lowercase();
+                words.iter().any(|word| lower.contains(word))
+            };
+            if is(&["ポイント", "points"]) {
+                payment.points += amount.abs();
+            } else if is(&["ギフトカード", "ギフト券", "gift card"]) {
+                // 「ギフト包装」のような手数料の行は残高の利用ではない
+                payment.gift_card += amount.abs();
+            } else if is(&[
+                "割引",
+                "クーポン",
+                "プロモーション",
+                "discount",
+                "promotion",
+                "coupon",
+            ]) {
+                payment.discount += amount.abs();
+            } else if is(&["小計", "subtotal"]) {
+                payment.subtotal = Some(amount);
+            } else if is(&["配送料", "shipping"]) {
+                payment.shipping += amount;
+            } else if (is(&["消費税", "tax"]) || label.starts_with("税")) && !is(&["before tax"])
+            {
+                // 「消費税合計」を注文合計と取り違えないよう合計より先に見る
+                payment.tax = Some(amount);
+            } else if is(&["請求額", "grand total"]) {
+                payment.grand_total = Some(amount);
+            } else if is(&["合計", "total"]) {
+                order_total = Some(amount);
+            }
+        }
+        // ポイントなどを使わなかった注文には「ご請求額」の行がない
+        if payment.grand_total.is_none() {
+            payment.grand_total = order_total;
+        }
+        payment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Payment;
+
+    #[test]
+    fn 注文の概要から支払いの内訳を読めるか確認() {
+        let payment = Payment::from_lines(&[
+            "商品の小計： ￥ 3,980",
+            "配送料・手数料： ￥ 410",
+            "割引： -￥ 410",
+            "クーポンの割引き： -￥ 200",
+            "注文合計： ￥ 3,780",
+            "Amazonポイント： -￥ 300",
+            "Amazonギフトカード： -￥ 1,000",
+            "ご請求額： ￥ 2,480",
+        ]);
+        assert_eq!(payment.subtotal, Some(3980));
+        assert_eq!(payment.shipping, 410);
+        assert_eq!(payment.discount, 610);
+        assert_eq!(payment.points, 300);
+        assert_eq!(payment.gift_card, 1000);
+        assert_eq!(payment.grand_total, Some(2480));
+    }
+    #[test]
+    fn 英語の項目名も読めるか確認() {
+        let payment = Payment::from_lines(&[
+            "Item Subtotal: ¥1,980",
+            "Shipping & Handling: ¥0",
+            "Promotion Applied: -¥200",
+            "Order Total: ¥1,780",
+        ]);
+        assert_eq!(payment.subtotal, Some(1980));
+        assert_eq!(payment.discount, 200);
+        assert_eq!(payment.grand_total, Some(1780));
+    }
+    #[test]
+    fn ご請求額がなければ注文合計を使うか確認() {
+        let payment = Payment::from_lines(&["商品の小計： ￥ 1,980", "注文合計： ￥ 1,980"]);
+        assert_eq!(payment.grand_total, Some(1980));
+        assert_eq!(payment.points, 0);
+    }
+    #[test]
+    fn 消費税合計を注文合計と間違えないか確認() {
+        let payment = Payment::from_lines(&[
+            "商品の小計： ￥ 3,080",
+            "注文合計： ￥ 3,080",
+            "消費税合計： ￥ 280",
+        ]);
+        assert_eq!(payment.tax, Some(280));
+        assert_eq!(payment.grand_total, Some(3080));
+
+        let payment = Payment::from_lines(&[
+            "Total before tax: ¥1,800",
+            "Estimated tax to be collected: ¥180",
+            "Order Total: ¥1,980",
+        ]);
+        assert_eq!(payment.tax, Some(180));
+        assert_eq!(payment.grand_total, Some(1980));
+    }
+    #[test]
+    fn ギフト包装をギフトカードと間違えないか確認() {
+        let payment = Payment::from_lines(&[
+            "商品の小計： ￥ 1,980",
+            "ギフト包装： ￥ 300",
+            "Amazonギフト券： -￥ 500",
+            "ご請求額： ￥ 1,780",
+        ]);
+        assert_eq!(payment.gift_card, 500);
+        assert_eq!(payment.grand_total, Some(1780));
+    }
+}
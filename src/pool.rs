@@ -0,0 +1,162 @@
+use crate::order::merge_archived;
+use crate::{
+    AmazonBrowser, BrowserConfig, CompletenessReport, Completion, ExtractOptions, Extraction, Log,
+    Order, YearCount,
+};
+use range::Range;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use thirtyfour::prelude::*;
+
+/// 複数のブラウザで年ごとに手分けして注文履歴を読み込む
+/// サインインは最初の1つだけで行い、残りにはそのクッキーを配る
+pub struct AmazonBrowserPool {
+    browsers: Vec<AmazonBrowser>,
+}
+
+impl AmazonBrowserPool {
+    /// `config.user_data_dir`に番号を付けたプロファイルで`size`個のブラウザを開く
+    /// 同時に読み込むのは`size`ページまで
+    pub async fn new(
+        email: &str,
+        password: &str,
+        config: &BrowserConfig,
+        size: usize,
+    ) -> WebDriverResult<AmazonBrowserPool> {
+        let mut browsers = vec![];
+        for n in 0..size.max(1) {
+            let config = BrowserConfig {
+                user_data_dir: format!("{}_{}", config.user_data_dir, n),
+                ..config.clone()
+            };
+            browsers.push(AmazonBrowser::with_config(email, password, &config).await?);
+        }
+        Ok(AmazonBrowserPool { browsers })
+    }
+    pub async fn extract(&mut self, range: &Range) -> WebDriverResult<Vec<Log>> {
+        let orders = self.extract_orders(range).await?;
+        Ok(orders.into_iter().flat_map(|order| order.items).collect())
+    }
+    pub async fn extract_orders(&mut self, range: &Range) -> WebDriverResult<Vec<Order>> {
+        let extraction = self
+            .extract_orders_with(range, &ExtractOptions::default())
+            .await?;
+        Ok(extraction.orders)
+    }
+    /// 中断された場合は読み終えた分までを返し、すべてのブラウザを閉じる
+    pub async fn extract_orders_with(
+        &mut self,
+        range: &Range,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<Extraction> {
+        use crate::utils::to_year;
+        let end = to_year(range.end());
+        let start = to_year(range.start());
+        let years = Mutex::new((start..=end).rev().collect::<VecDeque<i32>>());
+
+        if options.invoice_pdf_dir.is_some() {
+            for browser in &self.browsers {
+                browser.check_printable()?;
+            }
+        }
+        self.login().await?;
+        println!("読み込みを開始しました。");
+        let workers = self
+            .browsers
+            .iter_mut()
+            .map(|browser| browser.scrape_years(&years, range, options));
+        let mut orders = vec![];
+        let mut completeness = CompletenessReport::default();
+        let mut completion = Completion::Finished;
+        for (orders_of_worker, counts, interruption) in
+            futures::future::try_join_all(workers).await?
+        {
+            orders.extend(orders_of_worker);
+            completeness.years.extend(counts);
+            if let Some(interruption) = interruption {
+                completion = interruption;
+            }
+        }
+        if options.include_archived && completion == Completion::Finished {
+            if let Some(browser) = self.browsers.first_mut() {
+                let (archived, interruption) = browser.scrape_archived(range, options).await?;
+                merge_archived(&mut orders, archived);
+                if let Some(interruption) = interruption {
+                    completion = interruption;
+                }
+            }
+        }
+        if completion == Completion::Finished {
+            println!("読み込みが終了しました。");
+        } else {
+            println!("読み込みを中断しました。");
+            self.close().await?;
+        }
+
+        // 各ブラウザの結果は日付の降順なので、安定ソートで同日内の順序を保ったまま併合する
+        orders.sort_by(|a, b| b.ordered_at.cmp(&a.ordered_at));
+        completeness.years.sort_by(|a, b| b.year.cmp(&a.year));
+        Extraction::new(orders, completeness, completion, options)
+    }
+    pub async fn close(&mut self) -> WebDriverResult<()> {
+        let mut result = Ok(());
+        for browser in &mut self.browsers {
+            if let Err(e) = browser.close().await {
+                result = Err(e);
+            }
+        }
+        result
+    }
+    async fn login(&mut self) -> WebDriverResult<()> {
+        let (first, rest) = match self.browsers.split_first_mut() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        first.login().await?;
+        first.goto_home().await?; // Amazonは最初だけ例外的に飛ばされるページがある
+        let cookies = first.driver()?.get_cookies().await?;
+        for browser in rest {
+            // クッキーは同じドメインのページを開いていないと設定できない
+            browser.goto_home().await?;
+            let driver = browser.driver()?;
+            driver.delete_all_cookies().await?;
+            for cookie in &cookies {
+                driver.add_cookie(cookie.clone()).await?;
+            }
+            browser.goto_home().await?;
+        }
+        Ok(())
+    }
+}
+
+impl AmazonBrowser {
+    // 共有のキューから年を取り出せる限り読み込む
+    async fn scrape_years(
+        &mut self,
+        years: &Mutex<VecDeque<i32>>,
+        range: &Range,
+        options: &ExtractOptions,
+    ) -> WebDriverResult<(Vec<Order>, Vec<YearCount>, Option<Completion>)> {
+        let mut orders = vec![];
+        let mut counts = vec![];
+        loop {
+            if let Some(interruption) = options.interruption() {
+                return Ok((orders, counts, Some(interruption)));
+            }
+            // ロックを持ったままawaitしないよう取り出しだけで手放す
+            let next = years.lock().unwrap().pop_front();
+            let year = match next {
+                Some(year) => year,
+                None => break,
+            };
+            let (orders_of_year, count, interruption) =
+                self.scrape_year(&year, range, options).await?;
+            orders.extend(orders_of_year);
+            counts.push(count);
+            if interruption.is_some() {
+                return Ok((orders, counts, interruption));
+            }
+        }
+        Ok((orders, counts, None))
+    }
+}
@@ -0,0 +1,101 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// 商品ページへのリンクから読み取った商品の識別子
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProductRef {
+    Asin(String),
+    /// 書籍はASINがISBN-10と同じになっている
+    Isbn(String),
+    /// Prime Videoの`amzn1.dv.gti.…`のようにASINの形をしていないもの
+    Opaque(String),
+}
+
+impl ProductRef {
+    /// 商品ページ以外へのリンクならNone
+    pub fn parse(url: &str) -> Option<ProductRef> {
+        let path = Regex::new(
+            r"/(?:dp|gp/product|gp/aw/d|gp/offer-listing|exec/obidos/ASIN|gp/video/detail|gp/mas/dl/android)/([^/?&#]+)",
+        )
+        .unwrap();
+        let query = Regex::new(r"[?&](?:asin|ASIN)=([^&#]+)").unwrap();
+        let id = path
+            .captures(url)
+            .or_else(|| query.captures(url))
+            .map(|caps| caps[1].to_string())?;
+        Some(ProductRef::from_id(&id))
+    }
+    pub(crate) fn from_id(id: &str) -> ProductRef {
+        let isbn = Regex::new(r"^\d{9}[\dX]$").unwrap();
+        let asin = Regex::new(r"^[0-9A-Z]{10}$").unwrap();
+        if isbn.is_match(id) {
+            ProductRef::Isbn(id.to_string())
+        } else if asin.is_match(id) {
+            ProductRef::Asin(id.to_string())
+        } else {
+            ProductRef::Opaque(id.to_string())
+        }
+    }
+    pub fn id(&self) -> &str {
+        match self {
+            ProductRef::Asin(id) | ProductRef::Isbn(id) | ProductRef::Opaque(id) => id,
+        }
+    }
+    /// ISBN-10もそのままASINとして使える
+    pub fn asin(&self) -> Option<&str> {
+        match self {
+            ProductRef::Asin(id) | ProductRef::Isbn(id) => Some(id),
+            ProductRef::Opaque(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProductRef;
+
+    #[test]
+    fn いろいろな形の商品リンクを読めるか確認() {
+        let asin = Some(ProductRef::Asin("B07XJ8C8F5".to_string()));
+        assert_eq!(
+            ProductRef::parse("https://www.amazon.co.jp/gp/product/B07XJ8C8F5/ref=ppx_yo_dt_b_asin_title_o00_s00?ie=UTF8&psc=1"),
+            asin
+        );
+        assert_eq!(
+            ProductRef::parse("https://www.amazon.co.jp/gp/product/B07XJ8C8F5"),
+            asin
+        );
+        assert_eq!(
+            ProductRef::parse(
+                "https://www.amazon.co.jp/%E5%95%86%E5%93%81/dp/B07XJ8C8F5/ref=sr_1_1"
+            ),
+            asin
+        );
+        assert_eq!(
+            ProductRef::parse(
+                "https://www.amazon.co.jp/gp/offer-listing/B07XJ8C8F5?condition=used"
+            ),
+            asin
+        );
+        assert_eq!(
+            ProductRef::parse(
+                "https://www.amazon.co.jp/gp/product/4873118220/ref=ppx_yo_dt_b_asin_title_o00"
+            ),
+            Some(ProductRef::Isbn("4873118220".to_string()))
+        );
+        assert_eq!(
+            ProductRef::parse("https://www.amazon.co.jp/gp/video/detail/amzn1.dv.gti.1234abcd-0000-0000-0000-000000000000"),
+            Some(ProductRef::Opaque(
+                "amzn1.dv.gti.1234abcd-0000-0000-0000-000000000000".to_string()
+            ))
+        );
+        assert_eq!(
+            ProductRef::parse("https://www.amazon.co.jp/gp/digital/fiona/manage?asin=B00KINDLE1"),
+            Some(ProductRef::Asin("B00KINDLE1".to_string()))
+        );
+        assert_eq!(
+            ProductRef::parse("https://www.amazon.co.jp/gp/help/customer/display.html"),
+            None
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// ページ遷移の間隔の制限
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// 前回の遷移から最低限空ける時間
+    pub min_interval: Duration,
+    /// `min_interval`に加えて0からこの長さまでの時間をランダムに空ける
+    pub jitter: Duration,
+    pub max_pages_per_minute: Option<u32>,
+    /// 1日に開くページ数の上限。使い切ると翌日0時まで待ってから再開する
+    pub daily_budget: Option<u32>,
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit {
+            min_interval: Duration::from_secs(2),
+            jitter: Duration::from_secs(2),
+            max_pages_per_minute: Some(20),
+            daily_budget: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    last: Option<Instant>,
+    recent: VecDeque<Instant>,
+    day: Option<NaiveDate>,
+    used_today: u32,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            last: None,
+            recent: VecDeque::new(),
+            day: None,
+            used_today: 0,
+        }
+    }
+    /// 次の遷移の枠を予約し、それまで待つべき時間を返す
+    pub(crate) fn reserve(&mut self, now: Instant, local_now: NaiveDateTime) -> Duration {
+        let mut at = now;
+
+        if let Some(budget) = self.limit.daily_budget {
+            let today = local_now.date();
+            if self.day != Some(today) {
+                self.day = Some(today);
+                self.used_today = 0;
+            }
+            if self.used_today >= budget {
+                let tomorrow = today.succ_opt().unwrap();
+                let wait = (tomorrow.and_hms_opt(0, 0, 0).unwrap() - local_now)
+                    .to_std()
+                    .unwrap_or_default();
+                println!(
+                    "本日の上限({}ページ)に達したので{}まで待機します。",
+                    budget, tomorrow
+                );
+                at += wait;
+                self.day = Some(tomorrow);
+                self.used_today = 0;
+            }
+            self.used_today += 1;
+        }
+
+        if let Some(last) = self.last {
+            at = at.max(last + self.limit.min_interval + self.jitter());
+        }
+
+        if let Some(max) = self.limit.max_pages_per_minute {
+            while let Some(&oldest) = self.recent.front() {
+                if at.saturating_duration_since(oldest) < MINUTE {
+                    break;
+                }
+                self.recent.pop_front();
+            }
+            if self.recent.len() >= max as usize {
+                if let Some(oldest) = self.recent.pop_front() {
+                    at = at.max(oldest + MINUTE);
+                }
+            }
+            self.recent.push_back(at);
+        }
+
+        self.last = Some(at);
+        at.saturating_duration_since(now)
+    }
+    fn jitter(&self) -> Duration {
+        let max = self.limit.jitter.as_millis() as u64;
+        if max == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..=max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimit, RateLimiter};
+    use chrono::NaiveDate;
+    use std::time::{Duration, Instant};
+
+    fn limit() -> RateLimit {
+        RateLimit {
+            min_interval: Duration::from_secs(2),
+            jitter: Duration::ZERO,
+            max_pages_per_minute: None,
+            daily_budget: None,
+        }
+    }
+
+    #[test]
+    fn 最低間隔を空けて予約されるか確認() {
+        let mut limiter = RateLimiter::new(limit());
+        let now = Instant::now();
+        let local = NaiveDate::from_ymd_opt(2022, 1, 9)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert_eq!(limiter.reserve(now, local), Duration::ZERO);
+        assert_eq!(limiter.reserve(now, local), Duration::from_secs(2));
+        assert_eq!(limiter.reserve(now, local), Duration::from_secs(4));
+    }
+    #[test]
+    fn 一分あたりの上限を超えると待たされるか確認() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            min_interval: Duration::ZERO,
+            max_pages_per_minute: Some(2),
+            ..limit()
+        });
+        let now = Instant::now();
+        let local = NaiveDate::from_ymd_opt(2022, 1, 9)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert_eq!(limiter.reserve(now, local), Duration::ZERO);
+        assert_eq!(limiter.reserve(now, local), Duration::ZERO);
+        assert_eq!(limiter.reserve(now, local), Duration::from_secs(60));
+    }
+    #[test]
+    fn 日ごとの上限を使い切ると翌日まで待たされるか確認() {
+        let mut limiter = RateLimiter::new(RateLimit {
+            min_interval: Duration::ZERO,
+            daily_budget: Some(1),
+            ..limit()
+        });
+        let now = Instant::now();
+        let local = NaiveDate::from_ymd_opt(2022, 1, 9)
+            .unwrap()
+            .and_hms_opt(23, 0, 0)
+            .unwrap();
+        assert_eq!(limiter.reserve(now, local), Duration::ZERO);
+        assert_eq!(limiter.reserve(now, local), Duration::from_secs(60 * 60));
+    }
+}
@@ -0,0 +1,208 @@
+use thirtyfour::By;
+
+// ホーム
+pub(crate) const NAV_MESSAGE: By<'static> = By::Id("glow-ingress-line1");
+
+// サインイン
+pub(crate) const LOGIN_EMAIL: By<'static> = By::Id("ap_email");
+pub(crate) const LOGIN_EMAIL_BUTTON: By<'static> = By::Id("continue");
+pub(crate) const LOGIN_PASSWORD: By<'static> = By::Id("ap_password");
+pub(crate) const LOGIN_PASSWORD_BUTTON: By<'static> = By::Id("signInSubmit");
+
+// 注文履歴
+pub(crate) const YEAR_PROMPT: By<'static> = By::ClassName("a-dropdown-prompt");
+pub(crate) const YEAR_DROPDOWN: By<'static> = By::Id("a-autoid-1-announce");
+pub(crate) const YEAR_DROPDOWN_ITEM: By<'static> = By::ClassName("a-dropdown-item");
+pub(crate) const ORDER_COUNT: By<'static> = By::ClassName("num-orders");
+pub(crate) const ORDER_GROUP: By<'static> = By::ClassName("a-box-group");
+pub(crate) const ORDER_INFO: By<'static> = By::ClassName("a-span3");
+pub(crate) const ORDER_DATE: By<'static> = By::ClassName("a-color-secondary.value");
+pub(crate) const ORDER_LINKS: By<'static> = By::ClassName("a-unordered-list");
+pub(crate) const LINK: By<'static> = By::ClassName("a-link-normal");
+pub(crate) const NEXT_PAGE: By<'static> = By::ClassName("a-last");
+pub(crate) const NEXT_PAGE_DISABLED: By<'static> = By::ClassName("a-disabled.a-last");
+pub(crate) const ANCHOR: By<'static> = By::Tag("a");
+// 発送単位の枠(注文内容ページにもある)
+pub(crate) const SHIPMENT: By<'static> = By::ClassName("shipment");
+pub(crate) const SHIPMENT_TRACK_LINK: By<'static> =
+    By::XPath(".//a[contains(@href, 'ship-track') or contains(@href, 'progress-tracker')]");
+
+// 注文内容
+pub(crate) const ITEM: By<'static> = By::ClassName("a-fixed-left-grid-inner");
+pub(crate) const ITEM_QUANTITY: By<'static> = By::ClassName("item-view-qty");
+pub(crate) const ITEM_NAME_COLUMN: By<'static> = By::ClassName("a-col-right");
+pub(crate) const ITEM_PRICE: By<'static> = By::ClassName("a-color-price");
+// 商品の中にある出品者ページへのリンク(Amazonの販売なら無い)
+pub(crate) const ITEM_SELLER_LINK: By<'static> = By::XPath(".//a[contains(@href, 'seller=')]");
+
+// 「注文の概要」の1行(商品の小計、配送料、ポイントなど)
+pub(crate) const PAYMENT_LINE: By<'static> =
+    By::XPath("//div[@id='od-subtotals']//div[contains(@class, 'a-row')]");
+pub(crate) const PAYMENT_METHOD: By<'static> =
+    By::XPath("//*[contains(@class, 'pmts-payments-instrument-detail')]");
+
+// 領収書/購入明細書(テキスト全体から読む)
+pub(crate) const BODY: By<'static> = By::Tag("body");
+
+// デジタル注文の注文内容(表組みで、商品へのリンクを含む行が1商品)
+pub(crate) const DIGITAL_ITEM: By<'static> = By::XPath(
+    "//a[contains(@href, '/dp/') or contains(@href, '/gp/product/') or contains(@href, '/gp/video/') or contains(@href, '/gp/mas/')]/ancestor::tr[1]",
+);
+pub(crate) const DIGITAL_ITEM_LINK: By<'static> = By::XPath(
+    ".//a[contains(@href, '/dp/') or contains(@href, '/gp/product/') or contains(@href, '/gp/video/') or contains(@href, '/gp/mas/')]",
+);
+pub(crate) const DIGITAL_ITEM_PRICE: By<'static> = By::XPath("./td[last()]");
+
+// self_checkで検査する一覧
+// 必須でないもの(数量表記、最終ページの無効化された次へボタンなど)はページによって存在しないことがある
+pub(crate) struct Entry {
+    pub name: &'static str,
+    pub by: By<'static>,
+    pub required: bool,
+}
+
+pub(crate) const HOME: &[Entry] = &[Entry {
+    name: "NAV_MESSAGE",
+    by: NAV_MESSAGE,
+    required: true,
+}];
+
+pub(crate) const LOGIN: &[Entry] = &[
+    Entry {
+        name: "LOGIN_EMAIL",
+        by: LOGIN_EMAIL,
+        required: true,
+    },
+    Entry {
+        name: "LOGIN_EMAIL_BUTTON",
+        by: LOGIN_EMAIL_BUTTON,
+        required: true,
+    },
+    // パスワード欄はメールアドレス入力後の画面に出る
+    Entry {
+        name: "LOGIN_PASSWORD",
+        by: LOGIN_PASSWORD,
+        required: false,
+    },
+    Entry {
+        name: "LOGIN_PASSWORD_BUTTON",
+        by: LOGIN_PASSWORD_BUTTON,
+        required: false,
+    },
+];
+
+pub(crate) const HISTORY: &[Entry] = &[
+    Entry {
+        name: "YEAR_PROMPT",
+        by: YEAR_PROMPT,
+        required: true,
+    },
+    Entry {
+        name: "YEAR_DROPDOWN",
+        by: YEAR_DROPDOWN,
+        required: true,
+    },
+    Entry {
+        name: "YEAR_DROPDOWN_ITEM",
+        by: YEAR_DROPDOWN_ITEM,
+        required: false,
+    },
+    Entry {
+        name: "ORDER_COUNT",
+        by: ORDER_COUNT,
+        required: true,
+    },
+    Entry {
+        name: "ORDER_GROUP",
+        by: ORDER_GROUP,
+        required: true,
+    },
+    Entry {
+        name: "ORDER_INFO",
+        by: ORDER_INFO,
+        required: true,
+    },
+    Entry {
+        name: "ORDER_DATE",
+        by: ORDER_DATE,
+        required: true,
+    },
+    Entry {
+        name: "ORDER_LINKS",
+        by: ORDER_LINKS,
+        required: true,
+    },
+    Entry {
+        name: "LINK",
+        by: LINK,
+        required: true,
+    },
+    Entry {
+        name: "SHIPMENT",
+        by: SHIPMENT,
+        required: true,
+    },
+    // 配達済みの注文しかなければ表示されない
+    Entry {
+        name: "SHIPMENT_TRACK_LINK",
+        by: SHIPMENT_TRACK_LINK,
+        required: false,
+    },
+    Entry {
+        name: "NEXT_PAGE",
+        by: NEXT_PAGE,
+        required: false,
+    },
+    Entry {
+        name: "NEXT_PAGE_DISABLED",
+        by: NEXT_PAGE_DISABLED,
+        required: false,
+    },
+];
+
+pub(crate) const DETAILS: &[Entry] = &[
+    Entry {
+        name: "ITEM",
+        by: ITEM,
+        required: true,
+    },
+    Entry {
+        name: "ITEM_QUANTITY",
+        by: ITEM_QUANTITY,
+        required: false,
+    },
+    Entry {
+        name: "ITEM_NAME_COLUMN",
+        by: ITEM_NAME_COLUMN,
+        required: true,
+    },
+    Entry {
+        name: "ITEM_PRICE",
+        by: ITEM_PRICE,
+        required: true,
+    },
+    // Amazonが販売する商品しかない注文には出品者へのリンクがない
+    Entry {
+        name: "ITEM_SELLER_LINK",
+        by: ITEM_SELLER_LINK,
+        required: false,
+    },
+    Entry {
+        name: "PAYMENT_LINE",
+        by: PAYMENT_LINE,
+        required: true,
+    },
+    // ギフトカードやポイントだけで支払った注文には表示されない
+    Entry {
+        name: "PAYMENT_METHOD",
+        by: PAYMENT_METHOD,
+        required: false,
+    },
+];
+
+// 行の中で探すものは行が見つかったかどうかで代用する
+pub(crate) const DIGITAL_DETAILS: &[Entry] = &[Entry {
+    name: "DIGITAL_ITEM",
+    by: DIGITAL_ITEM,
+    required: true,
+}];
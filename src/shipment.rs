@@ -0,0 +1,221 @@
+use crate::{selector, AmazonBrowser, Order, OrderStatus};
+use chrono::{Datelike, NaiveDate};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thirtyfour::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShipmentStatus {
+    /// 発送準備中、またはお届け予定だけが表示されている
+    Preparing,
+    Shipped,
+    OutForDelivery,
+    Delivered,
+    Unknown,
+}
+
+/// 注文の中の発送単位。分割発送なら1注文に複数ある
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shipment {
+    pub status: ShipmentStatus,
+    pub delivered_at: Option<NaiveDate>,
+    /// 「お届け予定: 8月5日」のような表示のまま持つ
+    pub expected: Option<String>,
+    /// 以下は配送状況ページを開いたときだけ埋まる
+    pub carrier: Option<String>,
+    pub tracking_id: Option<String>,
+    pub tracking_url: Option<String>,
+}
+
+impl Shipment {
+    /// 発送単位の枠に表示されている文言から読む
+    /// 年が書かれていない日付は注文日以降で最も近い日とみなす
+    pub(crate) fn from_text(text: &str, ordered_at: NaiveDate) -> Shipment {
+        let status = if text.contains("配達済み") || text.contains("配達しました") {
+            ShipmentStatus::Delivered
+        } else if text.contains("配達中") {
+            ShipmentStatus::OutForDelivery
+        } else if text.contains("発送済み") || text.contains("発送しました") {
+            ShipmentStatus::Shipped
+        } else if text.contains("準備") || text.contains("予定") {
+            ShipmentStatus::Preparing
+        } else {
+            ShipmentStatus::Unknown
+        };
+        let delivered_at = if status == ShipmentStatus::Delivered {
+            date_in_text(text, ordered_at)
+        } else {
+            None
+        };
+        let expected = Regex::new(r"((?:お届け|到着)予定[^\n]*)")
+            .unwrap()
+            .captures(text)
+            .map(|caps| caps[1].trim().to_string());
+        Shipment {
+            status,
+            delivered_at,
+            expected,
+            carrier: None,
+            tracking_id: None,
+            tracking_url: None,
+        }
+    }
+    pub fn is_delivered(&self) -> bool {
+        self.status == ShipmentStatus::Delivered
+    }
+    /// 準備中か配送中。`Unknown`は返品や古い注文の見慣れない表示なので、届く途中とはみなさない
+    pub fn is_in_transit(&self) -> bool {
+        matches!(
+            self.status,
+            ShipmentStatus::Preparing | ShipmentStatus::Shipped | ShipmentStatus::OutForDelivery
+        )
+    }
+}
+
+impl Order {
+    /// キャンセルされておらず、準備中か配送中の発送がある
+    pub fn is_open(&self) -> bool {
+        self.status != OrderStatus::Cancelled
+            && self
+                .shipments
+                .iter()
+                .any(|shipment| shipment.is_in_transit())
+    }
+}
+
+impl AmazonBrowser {
+    /// 届いていない注文だけ注文内容ページと配送状況ページを開き直して発送の状況を更新し、
+    /// 更新後もまだ届いていない注文を返す
+    pub async fn track_open_orders(&mut self, orders: &mut [Order]) -> WebDriverResult<Vec<Order>> {
+        self.login().await?;
+        for order in orders.iter_mut().filter(|order| order.is_open()) {
+            self.open(&order.details_url).await?;
+            let page = self.driver()?.find_element(selector::BODY).await?;
+            order.shipments = read_shipments(&page, order.ordered_at).await?;
+            for shipment in &mut order.shipments {
+                let url = match (&shipment.tracking_url, shipment.is_delivered()) {
+                    (Some(url), false) => url.clone(),
+                    _ => continue,
+                };
+                self.open(&url).await?;
+                let text = self
+                    .driver()?
+                    .find_element(selector::BODY)
+                    .await?
+                    .text()
+                    .await?;
+                let (carrier, tracking_id) = tracking_from_text(&text);
+                shipment.carrier = carrier;
+                shipment.tracking_id = tracking_id;
+            }
+            println!("配送状況を更新: {}", order.id);
+        }
+        Ok(orders
+            .iter()
+            .filter(|order| order.is_open())
+            .cloned()
+            .collect())
+    }
+}
+
+// 注文履歴の1グループ、または注文内容ページの中にある発送単位をすべて読む
+pub(crate) async fn read_shipments(
+    parent: &WebElement<'_>,
+    ordered_at: NaiveDate,
+) -> WebDriverResult<Vec<Shipment>> {
+    let mut shipments = vec![];
+    for element in parent.find_elements(selector::SHIPMENT).await? {
+        let mut shipment = Shipment::from_text(&element.text().await?, ordered_at);
+        if let Ok(link) = element.find_element(selector::SHIPMENT_TRACK_LINK).await {
+            shipment.tracking_url = link.get_attribute("href").await?;
+        }
+        shipments.push(shipment);
+    }
+    Ok(shipments)
+}
+
+fn date_in_text(text: &str, ordered_at: NaiveDate) -> Option<NaiveDate> {
+    let full = Regex::new(r"(\d{4})[/年]\s*(\d{1,2})[/月]\s*(\d{1,2})").unwrap();
+    if let Some(caps) = full.captures(text) {
+        return NaiveDate::from_ymd_opt(
+            caps[1].parse().ok()?,
+            caps[2].parse().ok()?,
+            caps[3].parse().ok()?,
+        );
+    }
+    let short = Regex::new(r"(\d{1,2})月\s*(\d{1,2})日").unwrap();
+    let caps = short.captures(text)?;
+    let (month, day) = (caps[1].parse().ok()?, caps[2].parse().ok()?);
+    let date = NaiveDate::from_ymd_opt(ordered_at.year(), month, day)?;
+    if date < ordered_at {
+        NaiveDate::from_ymd_opt(ordered_at.year() + 1, month, day)
+    } else {
+        Some(date)
+    }
+}
+
+// 配送状況ページから配送業者と伝票番号を読む
+fn tracking_from_text(text: &str) -> (Option<String>, Option<String>) {
+    let carrier = Regex::new(r"(?m)(?:配送業者|Carrier)\s*[:：]?\s*(\S.*?)\s*$").unwrap();
+    let tracking_id =
+        Regex::new(r"(?:伝票番号|追跡ID|Tracking ID)\s*[:：]?\s*([0-9A-Za-z-]+)").unwrap();
+    (
+        carrier.captures(text).map(|caps| caps[1].to_string()),
+        tracking_id.captures(text).map(|caps| caps[1].to_string()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tracking_from_text, Shipment, ShipmentStatus};
+    use crate::{Order, OrderStatus};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn 発送の状況と配達日を読めるか確認() {
+        let ordered_at = NaiveDate::from_ymd_opt(2021, 12, 30).unwrap();
+        let delivered = Shipment::from_text("配達済み 1月2日\n配達しました", ordered_at);
+        assert_eq!(delivered.status, ShipmentStatus::Delivered);
+        assert_eq!(delivered.delivered_at, NaiveDate::from_ymd_opt(2022, 1, 2));
+
+        let shipped = Shipment::from_text("発送済み\nお届け予定: 1月4日", ordered_at);
+        assert_eq!(shipped.status, ShipmentStatus::Shipped);
+        assert_eq!(shipped.delivered_at, None);
+        assert_eq!(shipped.expected.as_deref(), Some("お届け予定: 1月4日"));
+
+        let delivered = Shipment::from_text("2021/12/31に配達しました", ordered_at);
+        assert_eq!(
+            delivered.delivered_at,
+            NaiveDate::from_ymd_opt(2021, 12, 31)
+        );
+    }
+    #[test]
+    fn キャンセルや状況の分からない発送は追跡しないか確認() {
+        let ordered_at = NaiveDate::from_ymd_opt(2021, 12, 30).unwrap();
+        let mut order = Order {
+            shipments: vec![Shipment::from_text("発送済み", ordered_at)],
+            ..Order::new("250-1".to_string(), ordered_at, String::new())
+        };
+        assert!(order.is_open());
+        order.status = OrderStatus::Cancelled;
+        assert!(!order.is_open());
+
+        let order = Order {
+            shipments: vec![Shipment::from_text("返品を受け付けました", ordered_at)],
+            ..Order::new("250-2".to_string(), ordered_at, String::new())
+        };
+        assert_eq!(order.shipments[0].status, ShipmentStatus::Unknown);
+        assert!(!order.is_open());
+    }
+    #[test]
+    fn 配送業者と伝票番号を読めるか確認() {
+        assert_eq!(
+            tracking_from_text("配送業者: ヤマト運輸\nお問い合わせ伝票番号: 1234-5678-9012"),
+            (
+                Some("ヤマト運輸".to_string()),
+                Some("1234-5678-9012".to_string())
+            )
+        );
+        assert_eq!(tracking_from_text("配達済み"), (None, None));
+    }
+}
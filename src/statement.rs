@@ -0,0 +1,408 @@
+use crate::order::{Order, OrderStatus};
+use crate::ItemStatus;
+use chrono::NaiveDate;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// カード明細の1行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Charge {
+    pub date: NaiveDate,
+    /// 返金は負の金額
+    pub amount: i32,
+    pub description: String,
+}
+
+impl Charge {
+    /// 利用店名がAmazonらしいか。明細には他の店の利用も混ざるので突き合わせの前に絞り込む
+    pub fn is_amazon(&self) -> bool {
+        let description = self.description.to_uppercase();
+        ["AMAZON", "ＡＭＡＺＯＮ", "アマゾン", "ｱﾏｿﾞﾝ"]
+            .iter()
+            .any(|name| description.contains(name))
+    }
+}
+
+/// カード明細CSVのどの列に何が入っているか。列番号は0始まり
+/// 日付が読めない行(見出しやカード名義の行、合計行)は読み飛ばす
+#[derive(Debug, Clone)]
+pub struct StatementFormat {
+    pub date_column: usize,
+    pub description_column: usize,
+    pub amount_column: usize,
+    /// chronoの書式。"2021/08/01"なら"%Y/%m/%d"
+    pub date_format: String,
+}
+
+impl StatementFormat {
+    pub fn new(date_column: usize, description_column: usize, amount_column: usize) -> Self {
+        StatementFormat {
+            date_column,
+            description_column,
+            amount_column,
+            date_format: "%Y/%m/%d".to_string(),
+        }
+    }
+    /// 楽天カード(利用日,利用店名・商品名,利用者,支払方法,利用金額,…)
+    pub fn rakuten() -> Self {
+        StatementFormat::new(0, 1, 4)
+    }
+    /// 三井住友カード(Vpass)。1行目はカード名義、以降は利用日,利用店名,利用金額,…
+    pub fn smbc() -> Self {
+        StatementFormat::new(0, 1, 2)
+    }
+    /// MyJCB(ご利用者,カテゴリ,ご利用日,ご利用先など,ご利用金額(円),…)
+    pub fn jcb() -> Self {
+        StatementFormat::new(2, 3, 4)
+    }
+}
+
+/// カード明細のCSVを読む。UTF-8として読めなければShift_JISとして読む
+pub fn import_card_statement<P: AsRef<Path>>(
+    path: P,
+    format: &StatementFormat,
+) -> io::Result<Vec<Charge>> {
+    let content = fs::read(path)?;
+    read_statement(&decode(&content), format).map_err(io::Error::from)
+}
+
+fn decode(content: &[u8]) -> String {
+    let content = content.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(content);
+    match std::str::from_utf8(content) {
+        Ok(text) => text.to_string(),
+        Err(_) => encoding_rs::SHIFT_JIS.decode(content).0.into_owned(),
+    }
+}
+
+pub(crate) fn read_statement(text: &str, format: &StatementFormat) -> csv::Result<Vec<Charge>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let mut charges = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let date = match record
+            .get(format.date_column)
+            .and_then(|date| NaiveDate::parse_from_str(date.trim(), &format.date_format).ok())
+        {
+            Some(date) => date,
+            None => continue,
+        };
+        let amount = match record.get(format.amount_column).and_then(parse_amount) {
+            Some(amount) => amount,
+            None => continue,
+        };
+        let description = record
+            .get(format.description_column)
+            .unwrap_or("")
+            .trim()
+            .to_string();
+        charges.push(Charge {
+            date,
+            amount,
+            description,
+        });
+    }
+    Ok(charges)
+}
+
+// "1,980"、"1,980円"、"￥1,980"、"-500"のような表記を読む
+fn parse_amount(text: &str) -> Option<i32> {
+    let text: String = text
+        .chars()
+        .filter(|c| !matches!(c, ',' | '円' | '￥' | '¥' | ' ' | '　'))
+        .map(|c| if c == '−' || c == '▲' { '-' } else { c })
+        .collect();
+    if text.is_empty() {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// 1つの注文に対応した請求。分割発送で別々に請求された場合は複数になる
+#[derive(Debug, Clone)]
+pub struct ChargeMatch {
+    pub order_id: String,
+    pub charges: Vec<Charge>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChargeReport {
+    pub matches: Vec<ChargeMatch>,
+    pub unmatched_charges: Vec<Charge>,
+    /// 請求が見つからなかった注文の注文番号
+    pub unmatched_orders: Vec<String>,
+    /// 返品の返金(負の金額)と、それが戻ってきた注文
+    pub refunds: Vec<ChargeMatch>,
+    pub unmatched_refunds: Vec<Charge>,
+}
+
+// 分割請求の組み合わせを探すときに見る請求の上限
+const MAX_SPLIT_CANDIDATES: usize = 16;
+
+/// 請求と注文を金額と日付で突き合わせる
+/// 明細のうちAmazonの利用(`Charge::is_amazon`)だけを対象とし、他の店の利用は結果に含めない
+/// 請求日は注文日の前日から`window_days`日後までを対象とし、まず1回の請求で注文の請求額に
+/// 一致するものを、次に分割発送として複数の請求の合計が一致するものを探す
+/// キャンセルされた注文と、ポイントやギフト券で全額支払った注文は請求がないので対象外
+/// 負の金額は返金として、注文日以降に`ItemStatus::Refund`の記録と同じ額のものを探す
+pub fn match_charges(charges: &[Charge], orders: &[Order], window_days: i64) -> ChargeReport {
+    let (charges, refunds): (Vec<&Charge>, Vec<&Charge>) = charges
+        .iter()
+        .filter(|charge| charge.is_amazon())
+        .partition(|charge| charge.amount >= 0);
+    let mut report = match_refunds(&refunds, orders);
+
+    let mut orders: Vec<(&Order, i32)> = orders
+        .iter()
+        .filter(|order| order.status != OrderStatus::Cancelled)
+        .filter_map(|order| charged_amount(order).map(|amount| (order, amount)))
+        .filter(|(_, amount)| *amount > 0)
+        .collect();
+    orders.sort_by_key(|(order, _)| order.ordered_at);
+
+    let in_window = |order: &Order, charge: &Charge| {
+        let days = (charge.date - order.ordered_at).num_days();
+        (-1..=window_days).contains(&days)
+    };
+    let mut used = vec![false; charges.len()];
+    let mut matched: Vec<Option<Vec<usize>>> = vec![None; orders.len()];
+
+    // 1回の請求で一致するもの。注文日に最も近い請求を採る
+    for (i, (order, amount)) in orders.iter().enumerate() {
+        let found = (0..charges.len())
+            .filter(|&j| !used[j] && charges[j].amount == *amount && in_window(order, charges[j]))
+            .min_by_key(|&j| (charges[j].date - order.ordered_at).num_days().abs());
+        if let Some(j) = found {
+            used[j] = true;
+            matched[i] = Some(vec![j]);
+        }
+    }
+    // 分割発送で別々に請求されたもの
+    for (i, (order, amount)) in orders.iter().enumerate() {
+        if matched[i].is_some() {
+            continue;
+        }
+        let candidates: Vec<usize> = (0..charges.len())
+            .filter(|&j| {
+                !used[j]
+                    && charges[j].amount > 0
+                    && charges[j].amount < *amount
+                    && in_window(order, charges[j])
+            })
+            .take(MAX_SPLIT_CANDIDATES)
+            .collect();
+        if let Some(split) = subset_with_sum(&candidates, &charges, *amount) {
+            for &j in &split {
+                used[j] = true;
+            }
+            matched[i] = Some(split);
+        }
+    }
+
+    for ((order, _), found) in orders.iter().zip(matched) {
+        match found {
+            Some(indices) => report.matches.push(ChargeMatch {
+                order_id: order.id.clone(),
+                charges: indices.into_iter().map(|j| charges[j].clone()).collect(),
+            }),
+            None => report.unmatched_orders.push(order.id.clone()),
+        }
+    }
+    report.unmatched_charges = charges
+        .iter()
+        .zip(&used)
+        .filter(|(_, used)| !**used)
+        .map(|(charge, _)| (*charge).clone())
+        .collect();
+    report
+}
+
+// 返金は返品の手続きから日が空くので期間は区切らない。注文日に近い注文から探し、
+// 返金の記録1件分か、まだ対応の付いていない返金の合計に一致すれば対応付ける
+fn match_refunds(refunds: &[&Charge], orders: &[Order]) -> ChargeReport {
+    let mut report = ChargeReport::default();
+    let mut pending: Vec<(&Order, Vec<i32>)> = orders
+        .iter()
+        .map(|order| {
+            let amounts = order
+                .items
+                .iter()
+                .filter(|item| item.status == ItemStatus::Refund)
+                .map(|item| item.price)
+                .collect();
+            (order, amounts)
+        })
+        .filter(|(_, amounts): &(&Order, Vec<i32>)| !amounts.is_empty())
+        .collect();
+    pending.sort_by(|a, b| b.0.ordered_at.cmp(&a.0.ordered_at));
+
+    let mut refunds = refunds.to_vec();
+    refunds.sort_by_key(|charge| charge.date);
+    for charge in refunds {
+        let found = pending.iter_mut().find(|(order, amounts)| {
+            order.ordered_at <= charge.date
+                && (amounts.contains(&charge.amount)
+                    || amounts.iter().sum::<i32>() == charge.amount)
+        });
+        match found {
+            Some((order, amounts)) => {
+                match amounts.iter().position(|amount| *amount == charge.amount) {
+                    Some(i) => {
+                        amounts.remove(i);
+                    }
+                    None => amounts.clear(),
+                }
+                report.refunds.push(ChargeMatch {
+                    order_id: order.id.clone(),
+                    charges: vec![charge.clone()],
+                });
+            }
+            None => report.unmatched_refunds.push(charge.clone()),
+        }
+    }
+    report
+}
+
+// カードに請求される額。請求額がなければキャンセル以外の商品の合計
+fn charged_amount(order: &Order) -> Option<i32> {
+    if let Some(total) = order.payment.as_ref().and_then(|p| p.grand_total) {
+        return Some(total);
+    }
+    if order.items.is_empty() {
+        return None;
+    }
+    Some(order.items.iter().map(|item| item.amount().max(0)).sum())
+}
+
+// 2件以上の請求で合計が`amount`になる組み合わせを探す
+fn subset_with_sum(candidates: &[usize], charges: &[&Charge], amount: i32) -> Option<Vec<usize>> {
+    (1u32..1 << candidates.len())
+        .filter(|mask| mask.count_ones() >= 2)
+        .map(|mask| {
+            (0..candidates.len())
+                .filter(|bit| mask & (1 << bit) != 0)
+                .map(|bit| candidates[bit])
+                .collect::<Vec<usize>>()
+        })
+        .filter(|subset| subset.iter().map(|&j| charges[j].amount).sum::<i32>() == amount)
+        .min_by_key(|subset| subset.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, match_charges, read_statement, Charge, StatementFormat};
+    use crate::order::Order;
+    use crate::{ItemKind, ItemStatus, Log, Payment};
+    use chrono::NaiveDate;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2021, 8, day).unwrap()
+    }
+    fn charge(day: u32, amount: i32) -> Charge {
+        Charge {
+            date: date(day),
+            amount,
+            description: "AMAZON.CO.JP".to_string(),
+        }
+    }
+    fn order(id: &str, day: u32, total: i32) -> Order {
+        Order {
+            payment: Some(Payment {
+                grand_total: Some(total),
+                ..Payment::default()
+            }),
+            ..Order::new(id.to_string(), date(day), String::new())
+        }
+    }
+
+    #[test]
+    fn 楽天カードの明細を読めるか確認() {
+        let text = "\
+\"利用日\",\"利用店名・商品名\",\"利用者\",\"支払方法\",\"利用金額\",\"支払手数料\",\"支払総額\"
+\"2021/08/01\",\"AMAZON.CO.JP\",\"本人\",\"1回払い\",\"1,980\",\"0\",\"1,980\"
+\"2021/08/03\",\"ｺﾝﾋﾞﾆ\",\"本人\",\"1回払い\",\"500\",\"0\",\"500\"
+";
+        let charges = read_statement(text, &StatementFormat::rakuten()).unwrap();
+        assert_eq!(charges.len(), 2);
+        assert_eq!(charges[0], charge(1, 1980));
+        assert!(charges[0].is_amazon());
+        assert!(!charges[1].is_amazon());
+    }
+    #[test]
+    fn shift_jisの明細を読めるか確認() {
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode("2021/08/01,アマゾン,1980,１,１,1980,\n");
+        let charges = read_statement(&decode(&bytes), &StatementFormat::smbc()).unwrap();
+        assert_eq!(charges.len(), 1);
+        assert_eq!(charges[0].description, "アマゾン");
+        assert_eq!(charges[0].amount, 1980);
+    }
+    #[test]
+    fn 分割請求も含めて突き合わせるか確認() {
+        let charges = vec![
+            charge(2, 1980),
+            charge(3, 1000),
+            charge(6, 2500),
+            charge(20, 700),
+        ];
+        let orders = vec![
+            order("250-1", 1, 1980),
+            order("250-2", 2, 3500),
+            order("250-3", 2, 4200),
+        ];
+        let report = match_charges(&charges, &orders, 14);
+        assert_eq!(report.matches.len(), 2);
+        assert_eq!(report.matches[0].order_id, "250-1");
+        assert_eq!(report.matches[1].order_id, "250-2");
+        assert_eq!(report.matches[1].charges.len(), 2);
+        assert_eq!(report.unmatched_orders, vec!["250-3".to_string()]);
+        assert_eq!(report.unmatched_charges, vec![charge(20, 700)]);
+    }
+    #[test]
+    fn amazon以外の利用は突き合わせないか確認() {
+        let store = Charge {
+            description: "ｺﾝﾋﾞﾆ".to_string(),
+            ..charge(1, 1980)
+        };
+        let report = match_charges(&[store, charge(3, 1980)], &[order("250-1", 1, 1980)], 14);
+        assert_eq!(report.matches.len(), 1);
+        assert_eq!(report.matches[0].charges, vec![charge(3, 1980)]);
+        assert!(report.unmatched_charges.is_empty());
+    }
+    #[test]
+    fn 返金を返品した注文と突き合わせるか確認() {
+        let mut returned = order("250-1", 1, 2970);
+        returned.items = vec![
+            Log {
+                price: 1980,
+                ..refund_item()
+            },
+            Log {
+                price: -1980,
+                status: ItemStatus::Refund,
+                ..refund_item()
+            },
+        ];
+        let charges = vec![charge(2, 2970), charge(25, -1980), charge(26, -500)];
+        let report = match_charges(&charges, &[returned], 14);
+        assert_eq!(report.matches.len(), 1);
+        assert_eq!(report.refunds.len(), 1);
+        assert_eq!(report.refunds[0].order_id, "250-1");
+        assert_eq!(report.unmatched_refunds, vec![charge(26, -500)]);
+        assert!(report.unmatched_charges.is_empty());
+    }
+    fn refund_item() -> Log {
+        Log {
+            hash: String::new(),
+            name: "お茶 500ml".to_string(),
+            price: 0,
+            purchased_at: "2021-08-01".to_string(),
+            kind: ItemKind::Physical,
+            status: ItemStatus::Returned,
+            fulfillment: Default::default(),
+            product: None,
+        }
+    }
+}
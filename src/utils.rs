@@ -1,9 +1,6 @@
-pub fn to_default<T>(owner: &mut Option<Box<T>>) -> T {
-    *::std::mem::replace(owner, None).unwrap()
-}
-pub fn to_option<T>(owner: &mut Option<Box<T>>, raw: T) {
-    let _ = ::std::mem::replace(owner, Some(Box::new(raw)));
-}
+use std::time::Duration;
+use thirtyfour::prelude::*;
+
 pub fn to_year(date: String) -> i32 {
     use chrono::prelude::*;
     NaiveDate::parse_from_str(&date, "%Y-%m-%d").unwrap().year()
@@ -12,3 +9,60 @@ pub fn to_naive_date(date: String) -> chrono::NaiveDate {
     use chrono::prelude::*;
     NaiveDate::parse_from_str(&date, "%Y-%m-%d").unwrap()
 }
+// "￥ 1,980"や"-¥100"のような表記から金額を読む。金額がなければNone
+pub fn parse_yen(text: &str) -> Option<i32> {
+    use regex::Regex;
+    let re = Regex::new(r"(-|−)?\s*[￥¥]\s*(-|−)?\s*([\d,]+)").unwrap();
+    let caps = re.captures(text)?;
+    let amount: i32 = caps.get(3)?.as_str().replace(',', "").parse().ok()?;
+    if caps.get(1).is_some() || caps.get(2).is_some() {
+        Some(-amount)
+    } else {
+        Some(amount)
+    }
+}
+pub async fn wait_element<'a>(
+    driver: &'a WebDriver,
+    by: By<'a>,
+    timeout: Duration,
+) -> WebDriverResult<WebElement<'a>> {
+    driver
+        .query(by)
+        .wait(timeout, Duration::from_millis(250))
+        .first()
+        .await
+}
+// 新しく開いたタブはswitch_toしただけでは読み込みを待たない
+pub async fn wait_ready(driver: &WebDriver, timeout: Duration) -> WebDriverResult<()> {
+    let started = std::time::Instant::now();
+    while *driver
+        .execute_script("return document.readyState;")
+        .await?
+        .value()
+        != "complete"
+    {
+        if started.elapsed() > timeout {
+            return Err(WebDriverError::Timeout(
+                "ページの読み込みが終わりません".to_string(),
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_yen;
+
+    #[test]
+    fn 金額を読めるか確認() {
+        assert_eq!(parse_yen("￥ 1,100"), Some(1100));
+        assert_eq!(parse_yen("価格: ¥0"), Some(0));
+        assert_eq!(parse_yen("  ￥1,234,567 "), Some(1234567));
+        assert_eq!(parse_yen("-￥ 300"), Some(-300));
+        assert_eq!(parse_yen("￥ -300"), Some(-300));
+        assert_eq!(parse_yen("無料"), None);
+        assert_eq!(parse_yen("--"), None);
+    }
+}